@@ -0,0 +1,310 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// arbitrary-precision integers (used in the big integer mode)
+// the magnitude is stored in base 2^32, the least significant digit first,
+// without leading zeros. Zero is never negative.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    neg: bool,
+    mag: Vec<u32>,
+}
+
+fn cmp_mag(lhs: &[u32], rhs: &[u32]) -> Ordering {
+    lhs.len()
+        .cmp(&rhs.len())
+        .then_with(|| lhs.iter().rev().cmp(rhs.iter().rev()))
+}
+
+fn add_mag(lhs: &[u32], rhs: &[u32]) -> Vec<u32> {
+    let mut ret = Vec::with_capacity(lhs.len().max(rhs.len()) + 1);
+    let mut carry = 0;
+    for i in 0..lhs.len().max(rhs.len()) {
+        let sum = *lhs.get(i).unwrap_or(&0) as u64 + *rhs.get(i).unwrap_or(&0) as u64 + carry;
+        ret.push(sum as u32);
+        carry = sum >> 32;
+    }
+    ret.push(carry as u32);
+    ret
+}
+
+// lhs must be greater than or equal to rhs
+fn sub_mag(lhs: &[u32], rhs: &[u32]) -> Vec<u32> {
+    let mut ret = Vec::with_capacity(lhs.len());
+    let mut borrow = 0;
+    for (i, l) in lhs.iter().enumerate() {
+        let diff = *l as i64 - *rhs.get(i).unwrap_or(&0) as i64 - borrow;
+        ret.push(diff as u32);
+        borrow = if diff < 0 { 1 } else { 0 };
+    }
+    ret
+}
+
+fn mul_mag(lhs: &[u32], rhs: &[u32]) -> Vec<u32> {
+    let mut ret = vec![0; lhs.len() + rhs.len()];
+    for (i, l) in lhs.iter().enumerate() {
+        let mut carry = 0;
+        for (j, r) in rhs.iter().enumerate() {
+            let cur = ret[i + j] as u64 + *l as u64 * *r as u64 + carry;
+            ret[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+        ret[i + rhs.len()] = carry as u32;
+    }
+    ret
+}
+
+// mag = mag * m + a
+fn mul_small_add(mag: &mut Vec<u32>, m: u32, a: u32) {
+    let mut carry = a as u64;
+    for d in mag.iter_mut() {
+        let cur = *d as u64 * m as u64 + carry;
+        *d = cur as u32;
+        carry = cur >> 32;
+    }
+    if carry != 0 {
+        mag.push(carry as u32);
+    }
+}
+
+fn div_rem_small(mag: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut quo = vec![0; mag.len()];
+    let mut rem = 0;
+    for i in (0..mag.len()).rev() {
+        let cur = (rem << 32) | mag[i] as u64;
+        quo[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    (quo, rem as u32)
+}
+
+// long division bit by bit (rhs must not be zero)
+fn div_rem_mag(lhs: &[u32], rhs: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if rhs.len() == 1 {
+        let (quo, rem) = div_rem_small(lhs, rhs[0]);
+        return (quo, vec![rem]);
+    }
+    let mut quo = vec![0; lhs.len()];
+    let mut rem: Vec<u32> = Vec::new();
+    for i in (0..lhs.len() * 32).rev() {
+        // rem = rem * 2 + (the i-th bit of lhs)
+        mul_small_add(&mut rem, 2, (lhs[i / 32] >> (i % 32)) & 1);
+        if cmp_mag(&rem, rhs) != Ordering::Less {
+            rem = sub_mag(&rem, rhs);
+            while rem.last() == Some(&0) {
+                rem.pop();
+            }
+            quo[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (quo, rem)
+}
+
+impl BigInt {
+    fn new(neg: bool, mut mag: Vec<u32>) -> Self {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        let neg = neg && !mag.is_empty();
+        BigInt { neg, mag }
+    }
+
+    pub fn from_i64(n: i64) -> Self {
+        let abs = n.unsigned_abs();
+        BigInt::new(n < 0, vec![abs as u32, (abs >> 32) as u32])
+    }
+
+    // None if the value does not fit in i64
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let abs = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0, |acc, d| (acc << 32) | *d as u64);
+        if !self.neg {
+            i64::try_from(abs).ok()
+        } else if abs <= i64::MAX as u64 + 1 {
+            Some((abs as i64).wrapping_neg())
+        } else {
+            None
+        }
+    }
+
+    // digits (without the sign and the prefix) in the radix
+    pub fn parse(digits: &str, radix: u32) -> Option<Self> {
+        if digits.is_empty() {
+            return None;
+        }
+        let mut mag = Vec::new();
+        for c in digits.chars() {
+            mul_small_add(&mut mag, radix, c.to_digit(radix)?);
+        }
+        Some(BigInt::new(false, mag))
+    }
+
+    // an approximation in f64 (may be infinite)
+    pub fn to_f64(&self) -> f64 {
+        let abs = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0.0, |acc, d| acc * 4294967296.0 + *d as f64);
+        if self.neg {
+            -abs
+        } else {
+            abs
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    // truncated division like i64: the remainder has the sign of self
+    // rhs must not be zero
+    pub fn div_rem(&self, rhs: &BigInt) -> (BigInt, BigInt) {
+        let (quo, rem) = div_rem_mag(&self.mag, &rhs.mag);
+        (
+            BigInt::new(self.neg != rhs.neg, quo),
+            BigInt::new(self.neg, rem),
+        )
+    }
+}
+
+impl<'a> Add<&'a BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: &BigInt) -> BigInt {
+        if self.neg == rhs.neg {
+            return BigInt::new(self.neg, add_mag(&self.mag, &rhs.mag));
+        }
+        match cmp_mag(&self.mag, &rhs.mag) {
+            Ordering::Less => BigInt::new(rhs.neg, sub_mag(&rhs.mag, &self.mag)),
+            _ => BigInt::new(self.neg, sub_mag(&self.mag, &rhs.mag)),
+        }
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.neg, self.mag.clone())
+    }
+}
+
+impl<'a> Sub<&'a BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: &BigInt) -> BigInt {
+        self + &-rhs
+    }
+}
+
+impl<'a> Mul<&'a BigInt> for &'a BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        BigInt::new(self.neg != rhs.neg, mul_mag(&self.mag, &rhs.mag))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // digits in base 10^9, the least significant first
+        let mut chunks = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (quo, rem) = div_rem_small(&mag, 1_000_000_000);
+            chunks.push(rem);
+            mag = quo;
+            while mag.last() == Some(&0) {
+                mag.pop();
+            }
+        }
+        if self.neg {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod bigint_tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        match s.strip_prefix('-') {
+            Some(digits) => -&BigInt::parse(digits, 10).unwrap(),
+            None => BigInt::parse(s, 10).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut fact = BigInt::from_i64(1);
+        for i in 1..=30 {
+            fact = &fact * &BigInt::from_i64(i);
+        }
+        assert_eq!(fact.to_string(), "265252859812191058636308480000000");
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        let (quo, rem) = b.div_rem(&a);
+        assert_eq!(
+            (quo.to_string(), rem.to_string()),
+            ("-8".to_string(), "-9000000000900000000090".to_string())
+        );
+        let (quo, rem) = a.div_rem(&BigInt::from_i64(-7));
+        assert_eq!(quo.to_string(), "-17636684144620811271604938270");
+        assert_eq!(rem.to_string(), "0");
+        assert!(b < a && -&a < a && big("-0").is_zero());
+    }
+
+    #[test]
+    fn test_conversion() {
+        for n in [0, 1, -1, i64::MAX, i64::MIN, 1 << 32].iter() {
+            let big = BigInt::from_i64(*n);
+            assert_eq!(big.to_string(), n.to_string());
+            assert_eq!(big.to_i64(), Some(*n));
+        }
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").to_i64(), None);
+        assert_eq!(BigInt::parse("ff", 16), Some(BigInt::from_i64(255)));
+        assert_eq!(BigInt::parse("12a", 10), None);
+    }
+}
@@ -1,94 +1,252 @@
-use std::io::{self, Write};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
 
 use crate::error::error_exit;
-use crate::lexer::TokenType;
+use crate::format;
+use crate::lexer::Token;
 use crate::parser::{Operation, Parser};
+use crate::value::{cmp_numbers, ArithOp, IntWidth, Value};
 use crate::var_map::VariableMap;
 
+// the maximum depth of nested function calls
+const MAX_CALL_DEPTH: usize = 10000;
+
 mod ffi {
     extern "C" {
         pub fn clock() -> ::libc::clock_t;
     }
 }
 
+// the number of bits to shift must be less than the bit width
+pub fn shift_amount(n: i64, int_width: IntWidth) -> Option<u32> {
+    if (0..int_width.bits() as i64).contains(&n) {
+        Some(n as u32)
+    } else {
+        None
+    }
+}
+
+// the label of JumpTable which the value n selects (labels.len() means the default label)
+pub fn jump_table_index(n: i64, min: i64, labels: &[Token]) -> usize {
+    match usize::try_from(n as i128 - min as i128) {
+        Ok(i) if i < labels.len() => i,
+        _ => labels.len(),
+    }
+}
+
+// the case which the switch value is equal to as if it were compared with each case
+// (floats with fractions and big integers are equal to no case)
+fn switch_value(val: &Value) -> Option<i64> {
+    match *val {
+        Value::Int(n) => Some(n),
+        Value::Float(f) if f.fract() == 0.0 && f >= i64::MIN as f64 && f < i64::MAX as f64 => {
+            Some(f as i64)
+        }
+        Value::Str(_) => error_exit(String::from("Type error: cannot apply == to str and int")),
+        _ => None,
+    }
+}
+
+// read a line from stdin without the newline
+// returns None at EOF
+fn read_line() -> Option<String> {
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => None,
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Some(line)
+        }
+        Err(e) => error_exit(format!("Failed to read input: {}", e)),
+    }
+}
+
+// numbers are compared with numbers and strings are compared with strings
+fn compare(op: &str, lhs: &Value, rhs: &Value) -> Ordering {
+    match (lhs, rhs) {
+        (Value::Str(l), Value::Str(r)) => l.cmp(r),
+        _ => cmp_numbers(lhs, rhs).unwrap_or_else(|| {
+            error_exit(format!(
+                "Type error: cannot apply {} to {} and {}",
+                op,
+                lhs.type_name(),
+                rhs.type_name()
+            ))
+        }),
+    }
+}
+
+fn arith(var_map: &mut VariableMap, op: ArithOp, dist: &Token, lhs: &Token, rhs: &Token) {
+    let lhs_val = var_map.get_value(lhs);
+    let rhs_val = var_map.get_value(rhs);
+    match var_map.int_width.arith(op, &lhs_val, &rhs_val) {
+        Ok(val) => var_map.set_value(dist, val),
+        Err(e) => error_exit(e),
+    }
+}
+
 // executer
 impl Parser {
     pub fn exec(&self, var_map: &mut VariableMap) {
         let t0 = unsafe { ffi::clock() };
 
         let mut pc = 0;
+        // return addresses and the variables which receive return values
+        let mut call_stack: Vec<(usize, Token)> = Vec::new();
         while pc < self.internal_code.len() {
             match self.internal_code[pc] {
                 Operation::Copy(ref dist, ref var) => {
-                    let val = var_map.get(var);
-                    var_map.set(dist, val);
+                    let val = var_map.get_value(var);
+                    var_map.set_value(dist, val);
                 }
                 Operation::Add(ref dist, ref lhs, ref rhs) => {
+                    match (var_map.get_value(lhs), var_map.get_value(rhs)) {
+                        // concatenation
+                        (Value::Str(l), Value::Str(r)) => {
+                            var_map.set_value(dist, Value::Str(l + &r))
+                        }
+                        _ => arith(var_map, ArithOp::Add, dist, lhs, rhs),
+                    }
+                }
+                Operation::Sub(ref dist, ref lhs, ref rhs) => {
+                    arith(var_map, ArithOp::Sub, dist, lhs, rhs);
+                }
+                Operation::Mul(ref dist, ref lhs, ref rhs) => {
+                    arith(var_map, ArithOp::Mul, dist, lhs, rhs);
+                }
+                Operation::Div(ref dist, ref lhs, ref rhs) => {
+                    arith(var_map, ArithOp::Div, dist, lhs, rhs);
+                }
+                Operation::Mod(ref dist, ref lhs, ref rhs) => {
+                    arith(var_map, ArithOp::Mod, dist, lhs, rhs);
+                }
+                Operation::BitAnd(ref dist, ref lhs, ref rhs) => {
                     let lhs_val = var_map.get(lhs);
                     let rhs_val = var_map.get(rhs);
-                    var_map.set(dist, lhs_val + rhs_val);
+                    var_map.set(dist, lhs_val & rhs_val);
                 }
-                Operation::Sub(ref dist, ref lhs, ref rhs) => {
+                Operation::BitOr(ref dist, ref lhs, ref rhs) => {
                     let lhs_val = var_map.get(lhs);
                     let rhs_val = var_map.get(rhs);
-                    var_map.set(dist, lhs_val - rhs_val);
+                    var_map.set(dist, lhs_val | rhs_val);
                 }
-                Operation::Mul(ref dist, ref lhs, ref rhs) => {
+                Operation::BitXor(ref dist, ref lhs, ref rhs) => {
                     let lhs_val = var_map.get(lhs);
                     let rhs_val = var_map.get(rhs);
-                    var_map.set(dist, lhs_val * rhs_val);
+                    var_map.set(dist, lhs_val ^ rhs_val);
                 }
-                Operation::Div(ref dist, ref lhs, ref rhs) => {
+                Operation::Shl(ref dist, ref lhs, ref rhs) => {
                     let lhs_val = var_map.get(lhs);
                     let rhs_val = var_map.get(rhs);
-                    if rhs_val == 0 {
-                        error_exit(String::from("Zero division error"));
+                    match shift_amount(rhs_val, var_map.int_width) {
+                        Some(n) => var_map.set(dist, lhs_val << n),
+                        None => error_exit(format!("Shift amount out of range: {}", rhs_val)),
                     }
-                    var_map.set(dist, lhs_val / rhs_val);
                 }
-                Operation::Eq(ref dist, ref lhs, ref rhs) => {
+                Operation::Shr(ref dist, ref lhs, ref rhs) => {
                     let lhs_val = var_map.get(lhs);
                     let rhs_val = var_map.get(rhs);
-                    var_map.set(dist, if lhs_val == rhs_val { 1 } else { 0 });
+                    match shift_amount(rhs_val, var_map.int_width) {
+                        Some(n) => var_map.set(dist, lhs_val >> n),
+                        None => error_exit(format!("Shift amount out of range: {}", rhs_val)),
+                    }
+                }
+                Operation::Eq(ref dist, ref lhs, ref rhs) => {
+                    let lhs_val = var_map.get_value(lhs);
+                    let rhs_val = var_map.get_value(rhs);
+                    let ord = compare("==", &lhs_val, &rhs_val);
+                    var_map.set(dist, if ord == Ordering::Equal { 1 } else { 0 });
                 }
                 Operation::Ne(ref dist, ref lhs, ref rhs) => {
-                    let lhs_val = var_map.get(lhs);
-                    let rhs_val = var_map.get(rhs);
-                    var_map.set(dist, if lhs_val != rhs_val { 1 } else { 0 });
+                    let lhs_val = var_map.get_value(lhs);
+                    let rhs_val = var_map.get_value(rhs);
+                    let ord = compare("!=", &lhs_val, &rhs_val);
+                    var_map.set(dist, if ord != Ordering::Equal { 1 } else { 0 });
                 }
                 Operation::Lt(ref dist, ref lhs, ref rhs) => {
-                    let lhs_val = var_map.get(lhs);
-                    let rhs_val = var_map.get(rhs);
-                    var_map.set(dist, if lhs_val < rhs_val { 1 } else { 0 });
+                    let lhs_val = var_map.get_value(lhs);
+                    let rhs_val = var_map.get_value(rhs);
+                    let ord = compare("<", &lhs_val, &rhs_val);
+                    var_map.set(dist, if ord == Ordering::Less { 1 } else { 0 });
                 }
                 Operation::Le(ref dist, ref lhs, ref rhs) => {
-                    let lhs_val = var_map.get(lhs);
-                    let rhs_val = var_map.get(rhs);
-                    var_map.set(dist, if lhs_val <= rhs_val { 1 } else { 0 });
+                    let lhs_val = var_map.get_value(lhs);
+                    let rhs_val = var_map.get_value(rhs);
+                    let ord = compare("<=", &lhs_val, &rhs_val);
+                    var_map.set(dist, if ord != Ordering::Greater { 1 } else { 0 });
+                }
+                Operation::Gt(ref dist, ref lhs, ref rhs) => {
+                    let lhs_val = var_map.get_value(lhs);
+                    let rhs_val = var_map.get_value(rhs);
+                    let ord = compare(">", &lhs_val, &rhs_val);
+                    var_map.set(dist, if ord == Ordering::Greater { 1 } else { 0 });
+                }
+                Operation::Ge(ref dist, ref lhs, ref rhs) => {
+                    let lhs_val = var_map.get_value(lhs);
+                    let rhs_val = var_map.get_value(rhs);
+                    let ord = compare(">=", &lhs_val, &rhs_val);
+                    var_map.set(dist, if ord != Ordering::Less { 1 } else { 0 });
                 }
                 Operation::Print(ref val_tok) => {
-                    match &val_tok.ty {
-                        TokenType::Ident | TokenType::NumLiteral(_) => {
-                            let val = var_map.get(val_tok);
-                            print!("{}", val);
-                        }
-                        TokenType::StrLiteral => {
-                            print!("{}", val_tok.string);
-                        }
-                        _ => error_exit(format!("Cannot print {}", val_tok.string)),
+                    print!("{}", var_map.get_value(val_tok));
+                    io::stdout().flush().unwrap();
+                }
+                Operation::Println(ref val_tok) => {
+                    println!("{}", var_map.get_value(val_tok));
+                }
+                Operation::Printf(ref fmt, ref args) => {
+                    let vals: Vec<Value> = args.iter().map(|arg| var_map.get_value(arg)).collect();
+                    // the format has been checked by the parser
+                    let pieces = format::parse_format(&fmt.string).unwrap();
+                    match format::format(&pieces, &vals, var_map.int_width) {
+                        Ok(s) => print!("{}", s),
+                        Err(e) => error_exit(e),
                     }
                     io::stdout().flush().unwrap();
                 }
-                Operation::Println(ref val_tok) => match &val_tok.ty {
-                    TokenType::Ident | TokenType::NumLiteral(_) => {
-                        let val = var_map.get(val_tok);
-                        println!("{}", val);
+                Operation::Len(ref dist, ref val_tok) => match var_map.str_len(val_tok) {
+                    Ok(len) => var_map.set(dist, len),
+                    Err(e) => error_exit(e),
+                },
+                Operation::ToInt(ref dist, ref val_tok) => {
+                    let val = var_map.get_value(val_tok);
+                    match var_map.int_width.to_int(&val) {
+                        Ok(val) => var_map.set_value(dist, val),
+                        Err(e) => error_exit(e),
                     }
-                    TokenType::StrLiteral => {
-                        println!("{}", val_tok.string);
+                }
+                Operation::ToFloat(ref dist, ref val_tok) => {
+                    match var_map.get_value(val_tok).to_float() {
+                        Ok(val) => var_map.set_value(dist, val),
+                        Err(e) => error_exit(e),
                     }
-                    _ => error_exit(format!("Cannot print {}", val_tok.string)),
-                },
+                }
+                Operation::Input(ref dist) => {
+                    let line = read_line()
+                        .unwrap_or_else(|| error_exit(String::from("input: unexpected EOF")));
+                    match var_map.int_width.parse(line.trim()) {
+                        Some(val) => var_map.set_value(dist, val),
+                        None => error_exit(format!("input: invalid integer: {}", line)),
+                    }
+                }
+                // an empty string is returned at EOF
+                Operation::InputLine(ref dist) => {
+                    let line = read_line().unwrap_or_default();
+                    var_map.set_value(dist, Value::Str(line));
+                }
+                Operation::Eof(ref dist) => {
+                    let eof = match io::stdin().lock().fill_buf() {
+                        Ok(buf) => buf.is_empty(),
+                        Err(e) => error_exit(format!("Failed to read input: {}", e)),
+                    };
+                    var_map.set(dist, if eof { 1 } else { 0 });
+                }
                 Operation::Goto(ref label) => {
                     pc = var_map.label_get(label) as usize;
                     continue;
@@ -100,22 +258,70 @@ impl Parser {
                         continue;
                     }
                 }
+                Operation::JumpTable(ref val, min, ref labels, ref default) => {
+                    let index = match switch_value(&var_map.get_value(val)) {
+                        Some(n) => jump_table_index(n, min, labels),
+                        None => labels.len(),
+                    };
+                    let label = labels.get(index).unwrap_or(default);
+                    pc = var_map.label_get(label) as usize;
+                    continue;
+                }
                 Operation::Time => unsafe {
                     println!("time: {}", ffi::clock() - t0);
                 },
-                Operation::ArrayNew(ref ident, ref size_tok) => {
-                    let size = var_map.get(size_tok) as usize;
-                    var_map.array_init(ident, size);
+                Operation::ArrayNew(ref ident, ref size_toks, ref val_tok) => {
+                    let dims: Vec<i64> = size_toks.iter().map(|tok| var_map.get(tok)).collect();
+                    let val = var_map.get(val_tok);
+                    if let Err(e) = var_map.array_init(ident, &dims, val) {
+                        error_exit(e);
+                    }
                 }
-                Operation::ArrayGet(ref dist, ref ident, ref index_tok) => {
-                    let index = var_map.get(index_tok) as usize;
-                    let val = var_map.array_get(ident, index);
+                Operation::ArrayGet(ref dist, ref ident, ref index_toks) => {
+                    let indices: Vec<i64> = index_toks.iter().map(|tok| var_map.get(tok)).collect();
+                    let val = var_map.array_get(ident, &indices);
                     var_map.set(dist, val);
                 }
-                Operation::ArraySet(ref ident, ref index_tok, ref val_tok) => {
-                    let index = var_map.get(index_tok) as usize;
+                Operation::ArraySet(ref ident, ref index_toks, ref val_tok) => {
+                    let indices: Vec<i64> = index_toks.iter().map(|tok| var_map.get(tok)).collect();
                     let val = var_map.get(val_tok);
-                    var_map.array_set(ident, index, val);
+                    var_map.array_set(ident, &indices, val);
+                }
+                Operation::ArrayLen(ref dist, ref ident) => {
+                    let len = var_map.array_len(ident);
+                    var_map.set(dist, len);
+                }
+                Operation::ArrayCopy(ref dist, ref ident) => {
+                    var_map.array_copy(dist, ident);
+                }
+                Operation::ArrayEq(ref dist, ref lhs, ref rhs) => {
+                    let eq = var_map.array_eq(lhs, rhs);
+                    var_map.set(dist, if eq { 1 } else { 0 });
+                }
+                Operation::Call(ref dist, ref name, ref args) => {
+                    let func = &self.functions[&name.string];
+                    if var_map.frame_depth() >= MAX_CALL_DEPTH {
+                        error_exit(format!("Stack overflow in function {}", name.string));
+                    }
+                    // evaluate arguments in the caller's frame
+                    let vals: Vec<Value> = args.iter().map(|arg| var_map.get_value(arg)).collect();
+                    var_map.push_frame();
+                    for (param, val) in func.params.iter().zip(vals) {
+                        var_map.set_value(param, val);
+                    }
+                    call_stack.push((pc + 1, dist.clone()));
+                    pc = var_map.label_get(&func.label) as usize;
+                    continue;
+                }
+                Operation::Ret(ref val_tok) => {
+                    let val = var_map.get_value(val_tok);
+                    var_map.pop_frame();
+                    let (ret_pc, dist) = call_stack
+                        .pop()
+                        .unwrap_or_else(|| error_exit(String::from("return outside of function")));
+                    var_map.set_value(&dist, val);
+                    pc = ret_pc;
+                    continue;
                 }
                 Operation::Nop => (),
             }
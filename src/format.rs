@@ -0,0 +1,279 @@
+use crate::value::{IntWidth, Value};
+use std::convert::TryFrom;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// format strings of printf
+// %[flags][width][.precision]conversion
+//   flags: "-" (left-justify), "0" (pad with zeros)
+//   conversion: d (decimal), x / X (hexadecimal), b (binary), c (character), s (any value),
+//               f (fixed-point number with 6 or precision digits after the point)
+//   "%%" prints "%"
+#[derive(Debug, PartialEq, Eq)]
+pub enum Piece {
+    Text(String),
+    Spec(Spec),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Spec {
+    left: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    conv: char,
+}
+
+// width and precision are at most this
+const MAX_WIDTH: usize = 1000;
+
+// the digits of width or precision (0 if there are no digits)
+fn parse_width(chars: &mut Peekable<Chars>, name: &str) -> Result<usize, String> {
+    let mut n: usize = 0;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        n = n
+            .checked_mul(10)
+            .and_then(|n| n.checked_add(d as usize))
+            .filter(|n| *n <= MAX_WIDTH)
+            .ok_or_else(|| {
+                format!(
+                    "{} of a format specifier must be at most {}",
+                    name, MAX_WIDTH
+                )
+            })?;
+        chars.next();
+    }
+    Ok(n)
+}
+
+pub fn parse_format(fmt: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            text.push('%');
+            continue;
+        }
+        let mut spec = Spec {
+            left: false,
+            zero: false,
+            width: 0,
+            precision: None,
+            conv: ' ',
+        };
+        while let Some(&c) = chars.peek() {
+            match c {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        spec.width = parse_width(&mut chars, "Width")?;
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(parse_width(&mut chars, "Precision")?);
+        }
+        spec.conv = match chars.next() {
+            Some('f') => 'f',
+            Some(c)
+                if spec.precision.is_some() && matches!(c, 'd' | 'x' | 'X' | 'b' | 'c' | 's') =>
+            {
+                return Err(format!("Precision cannot be used with %{}", c))
+            }
+            Some(c) if matches!(c, 'd' | 'x' | 'X' | 'b' | 'c' | 's') => c,
+            Some(c) => return Err(format!("Invalid format specifier: %{}", c)),
+            None => return Err(String::from("Incomplete format specifier at the end")),
+        };
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+            text = String::new();
+        }
+        pieces.push(Piece::Spec(spec));
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+// the number of values which the format string takes
+pub fn count_specs(pieces: &[Piece]) -> usize {
+    pieces
+        .iter()
+        .filter(|piece| matches!(piece, Piece::Spec(_)))
+        .count()
+}
+
+// negative integers are shown in two's complement of the width
+fn to_unsigned(n: i64, int_width: IntWidth) -> u64 {
+    n as u64 & (u64::MAX >> (64 - int_width.bits()))
+}
+
+fn format_value(spec: &Spec, val: &Value, int_width: IntWidth) -> Result<String, String> {
+    let s = match (spec.conv, val) {
+        ('s', val) => val.to_string(),
+        ('d', Value::Int(n)) => n.to_string(),
+        ('d', Value::Big(n)) => n.to_string(),
+        ('x', Value::Int(n)) => format!("{:x}", to_unsigned(*n, int_width)),
+        ('X', Value::Int(n)) => format!("{:X}", to_unsigned(*n, int_width)),
+        ('b', Value::Int(n)) => format!("{:b}", to_unsigned(*n, int_width)),
+        ('f', val) if val.is_number() => {
+            format!("{:.*}", spec.precision.unwrap_or(6), val.to_f64().unwrap())
+        }
+        ('c', Value::Int(n)) => match u32::try_from(*n).ok().and_then(std::char::from_u32) {
+            Some(c) => c.to_string(),
+            None => return Err(format!("Invalid character code: {}", n)),
+        },
+        (conv, val) => {
+            return Err(format!(
+                "Type error: cannot format {} with %{}",
+                val.type_name(),
+                conv
+            ))
+        }
+    };
+    let len = s.chars().count();
+    if len >= spec.width {
+        return Ok(s);
+    }
+    let padding = spec.width - len;
+    Ok(if spec.left {
+        s + &" ".repeat(padding)
+    } else if spec.zero && spec.conv != 's' && spec.conv != 'c' {
+        // zeros are put after the sign
+        match s.strip_prefix('-') {
+            Some(digits) => format!("-{}{}", "0".repeat(padding), digits),
+            None => "0".repeat(padding) + &s,
+        }
+    } else {
+        " ".repeat(padding) + &s
+    })
+}
+
+pub fn format(pieces: &[Piece], vals: &[Value], int_width: IntWidth) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut vals = vals.iter();
+    for piece in pieces {
+        match piece {
+            Piece::Text(s) => ret.push_str(s),
+            Piece::Spec(spec) => {
+                let val = vals
+                    .next()
+                    .ok_or_else(|| String::from("Too few arguments for the format string"))?;
+                ret.push_str(&format_value(spec, val, int_width)?);
+            }
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod format_tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let cases = [
+            ("%d items", vec![Value::Int(3)], "3 items"),
+            (
+                "[%5d][%-5d][%05d]",
+                vec![Value::Int(-42); 3],
+                "[  -42][-42  ][-0042]",
+            ),
+            ("%x %X %08b", vec![Value::Int(255); 3], "ff FF 11111111"),
+            (
+                "%c%c 100%%",
+                vec![Value::Int(104), Value::Int(105)],
+                "hi 100%",
+            ),
+            (
+                "%s=%3s|%-4s|",
+                vec![
+                    Value::Str(String::from("a")),
+                    Value::Int(1),
+                    Value::Str(String::from("bc")),
+                ],
+                "a=  1|bc  |",
+            ),
+            (
+                "%f|%.2f|%8.3f|%-6.1f|%.0f",
+                vec![
+                    Value::Float(2.71875),
+                    Value::Float(-2.005),
+                    Value::Float(1.0 / 3.0),
+                    Value::Int(7),
+                    Value::Float(2.5),
+                ],
+                "2.718750|-2.00|   0.333|7.0   |2",
+            ),
+            (
+                "%s %s",
+                vec![Value::Float(0.5), Value::Float(100.0)],
+                "0.5 100.0",
+            ),
+        ];
+        for (fmt, vals, expected) in cases.iter() {
+            let pieces = parse_format(fmt).unwrap();
+            assert_eq!(
+                format(&pieces, vals, IntWidth::I32),
+                Ok(expected.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_format_negative_hex() {
+        let pieces = parse_format("%x %X %b").unwrap();
+        let vals = vec![Value::Int(-1), Value::Int(-256), Value::Int(-2)];
+        assert_eq!(
+            format(&pieces, &vals, IntWidth::I32),
+            Ok(format!("ffffffff FFFFFF00 {}0", "1".repeat(31)))
+        );
+        assert_eq!(
+            format(&pieces, &vals, IntWidth::I64),
+            Ok(format!(
+                "ffffffffffffffff FFFFFFFFFFFFFF00 {}0",
+                "1".repeat(63)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        assert!(parse_format("%q").is_err());
+        assert!(parse_format("abc %").is_err());
+        assert!(parse_format("%.2d").is_err());
+        assert_eq!(
+            parse_format("%.999999999f"),
+            Err(String::from(
+                "Precision of a format specifier must be at most 1000"
+            ))
+        );
+        assert_eq!(
+            parse_format("%999999999999999999999999d"),
+            Err(String::from(
+                "Width of a format specifier must be at most 1000"
+            ))
+        );
+        assert!(parse_format("%1000d%.1000f").is_ok());
+        let pieces = parse_format("%c").unwrap();
+        assert_eq!(
+            format(&pieces, &[Value::Int(0x1_0000_0041)], IntWidth::I64),
+            Err(String::from("Invalid character code: 4294967361"))
+        );
+        assert_eq!(
+            format(&pieces, &[Value::Int(-1)], IntWidth::I64),
+            Err(String::from("Invalid character code: -1"))
+        );
+        let pieces = parse_format("%d").unwrap();
+        assert_eq!(count_specs(&pieces), 1);
+        assert!(format(&pieces, &[Value::Str(String::from("a"))], IntWidth::I32).is_err());
+    }
+}
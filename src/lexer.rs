@@ -1,4 +1,5 @@
-use crate::error::error_exit;
+use crate::bigint::BigInt;
+use crate::value::{IntWidth, Value};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Token {
@@ -11,18 +12,28 @@ pub struct Token {
 pub enum TokenType {
     Simbol,
     Ident,
-    NumLiteral(i32),
+    NumLiteral(i64),
+    // integers which do not fit in i64 (only in the big integer mode)
+    BigLiteral(BigInt),
+    // the bits of a finite f64 (f64 is neither Eq nor Hash)
+    FloatLiteral(u64),
     StrLiteral,
 }
 
-pub fn dump_token(tok: &Token) -> String {
+pub fn dump_token(tok: &Token, int_width: IntWidth) -> String {
     match &tok.ty {
         TokenType::Simbol => {
             format!("{}(Simbol)", tok.string)
         }
         TokenType::Ident => tok.string.to_string(),
         TokenType::NumLiteral(_) => {
-            format!("i32 {}", tok.string)
+            format!("{} {}", int_width.name(), tok.string)
+        }
+        TokenType::BigLiteral(_) => {
+            format!("big {}", tok.string)
+        }
+        TokenType::FloatLiteral(_) => {
+            format!("f64 {}", tok.string)
         }
         TokenType::StrLiteral => {
             format!("Str \"{}\"", tok.string)
@@ -47,7 +58,7 @@ impl Token {
         }
     }
 
-    pub fn new_num(n: i32, line: Option<i32>) -> Self {
+    pub fn new_num(n: i64, line: Option<i32>) -> Self {
         Token {
             string: n.to_string(),
             ty: TokenType::NumLiteral(n),
@@ -55,8 +66,36 @@ impl Token {
         }
     }
 
+    // true and false are 1 and 0 (the type checker sees the string)
+    pub fn new_bool(b: bool, line: Option<i32>) -> Self {
+        Token {
+            string: String::from(if b { "true" } else { "false" }),
+            ty: TokenType::NumLiteral(b as i64),
+            line,
+        }
+    }
+
+    // literals of numbers
+    pub fn from_value(val: &Value, line: Option<i32>) -> Self {
+        match val {
+            Value::Int(n) => Token::new_num(*n, line),
+            Value::Big(n) => Token {
+                string: n.to_string(),
+                ty: TokenType::BigLiteral(n.clone()),
+                line,
+            },
+            Value::Float(f) => Token {
+                string: val.to_string(),
+                ty: TokenType::FloatLiteral(f.to_bits()),
+                line,
+            },
+            Value::Str(_) => panic!(),
+        }
+    }
+
+    // string literals never match keywords or symbols
     pub fn matches(&self, s: &str) -> bool {
-        self.string == s
+        self.ty != TokenType::StrLiteral && self.string == s
     }
 }
 
@@ -79,6 +118,7 @@ fn is_normal_symbol(c: char) -> bool {
             | '%'
             | '&'
             | '~'
+            | '^'
             | '|'
             | '<'
             | '>'
@@ -89,12 +129,21 @@ fn is_normal_symbol(c: char) -> bool {
     )
 }
 
+// symbols which consist of multiple characters
+// longer ones must come first
+const MULTI_CHAR_SYMBOLS: [&str; 18] = [
+    "<<=", ">>=", "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "&&",
+    "||", "<<", ">>",
+];
+
 #[derive(Debug)]
 pub struct Lexer {
     txt: String,
     pos: usize,
     line: i32,
     pub tokens: Vec<Token>,
+    // number literals must be in the range of the width
+    pub int_width: IntWidth,
 }
 
 impl Lexer {
@@ -102,8 +151,9 @@ impl Lexer {
         Lexer {
             txt: prg,
             pos: 0,
-            line: 0,
+            line: 1,
             tokens: Vec::new(),
+            int_width: IntWidth::I32,
         }
     }
 
@@ -111,10 +161,213 @@ impl Lexer {
         self.txt[self.pos..].chars().next().unwrap()
     }
 
-    pub fn lex(&mut self) {
+    fn skip_alphanumeric(&mut self) {
+        while self.pos < self.txt.len()
+            && (self.next_char().is_alphanumeric() || self.next_char() == '_')
+        {
+            // non-ASCII letters take several bytes
+            self.pos += self.next_char().len_utf8();
+        }
+    }
+
+    // numerical literals: 123, 1_000, 0x1F, 0b1010, 3.14, 6.02e23
+    fn lex_number(&mut self) -> Result<Value, String> {
+        let start_pos = self.pos;
+        self.skip_alphanumeric();
+        // a digit must follow "." in floats
+        let is_float = self.txt[start_pos..self.pos]
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '_')
+            && self.txt[self.pos..].starts_with('.')
+            && self.txt[self.pos + 1..].starts_with(|c: char| c.is_ascii_digit());
+        if is_float {
+            self.pos += 1; // "."
+            self.skip_alphanumeric();
+            // the sign of the exponent
+            if self.txt[start_pos..self.pos].ends_with(['e', 'E'])
+                && self.txt[self.pos..].starts_with(['+', '-'])
+            {
+                self.pos += 1;
+                self.skip_alphanumeric();
+            }
+        }
+        let literal = &self.txt[start_pos..self.pos];
+        let invalid = format!(
+            "Lexer error: Invalid number literal {} at line {}",
+            literal, self.line
+        );
+        if is_float {
+            // "_" is allowed only between digits
+            let chars: Vec<char> = literal.chars().collect();
+            let misplaced = chars.windows(2).any(|w| match (w[0], w[1]) {
+                ('_', c) | (c, '_') => !c.is_ascii_digit() && c != '_',
+                _ => false,
+            });
+            if misplaced || literal.ends_with('_') {
+                return Err(invalid);
+            }
+            return match literal.replace('_', "").parse::<f64>() {
+                Ok(f) if f.is_finite() => Ok(Value::Float(f)),
+                Ok(_) => Err(format!(
+                    "Lexer error: Number literal {} is out of range at line {}",
+                    literal, self.line
+                )),
+                Err(_) => Err(invalid),
+            };
+        }
+        let (radix, body) = match literal.get(..2) {
+            Some("0x") | Some("0X") => (16, &literal[2..]),
+            Some("0b") | Some("0B") => (2, &literal[2..]),
+            _ => (10, literal),
+        };
+        // "_" is allowed only between digits
+        if body.is_empty() || body.starts_with('_') || body.ends_with('_') {
+            return Err(invalid);
+        }
+        let digits = body.replace('_', "");
+        if !digits.chars().all(|c| c.is_digit(radix)) {
+            return Err(invalid);
+        }
+        match i64::from_str_radix(&digits, radix) {
+            Ok(n) if self.int_width.contains(n) => Ok(Value::Int(n)),
+            // literals never overflow in the big integer mode
+            _ if self.int_width == IntWidth::Big => {
+                Ok(Value::from_big(BigInt::parse(&digits, radix).unwrap()))
+            }
+            _ => Err(format!(
+                "Lexer error: Number literal {} is out of range at line {}",
+                literal, self.line
+            )),
+        }
+    }
+
+    // character literals: 'a', '\n'
+    fn lex_char(&mut self) -> Result<i64, String> {
+        self.pos += 1; // "'"
+        let c = match self.txt[self.pos..].chars().next() {
+            Some('\\') => Some(self.lex_escape()?),
+            Some(c) if c != '\'' && c != '\n' => {
+                self.pos += c.len_utf8();
+                Some(c)
+            }
+            _ => None,
+        };
+        match c {
+            Some(c) if self.txt[self.pos..].starts_with('\'') => {
+                self.pos += 1; // "'"
+                Ok(c as i64)
+            }
+            _ => Err(format!(
+                "Lexer error: Invalid character literal at line {}",
+                self.line
+            )),
+        }
+    }
+
+    // escape sequences in character literals and string literals
+    // self.pos points to "\\"
+    fn lex_escape(&mut self) -> Result<char, String> {
+        self.pos += 1; // "\\"
+        let c = match self.txt[self.pos..].chars().next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('\'') => '\'',
+            Some('"') => '"',
+            // \xNN
+            Some('x') => {
+                let digits = self.txt[self.pos + 1..].get(..2).unwrap_or("");
+                let code = match u32::from_str_radix(digits, 16) {
+                    Ok(code) if digits.chars().all(|c| c.is_ascii_hexdigit()) => code,
+                    _ => {
+                        return Err(format!(
+                            "Lexer error: \\x must be followed by 2 hexadecimal digits at line {}",
+                            self.line
+                        ))
+                    }
+                };
+                self.pos += 2;
+                char::from_u32(code).unwrap()
+            }
+            // \u{N...}
+            Some('u') => {
+                let invalid = format!(
+                    "Lexer error: Invalid unicode escape sequence at line {}",
+                    self.line
+                );
+                if !self.txt[self.pos + 1..].starts_with('{') {
+                    return Err(invalid);
+                }
+                let len = match self.txt[self.pos + 2..].find('}') {
+                    Some(len) => len,
+                    None => return Err(invalid),
+                };
+                let digits = &self.txt[self.pos + 2..self.pos + 2 + len];
+                if digits.is_empty()
+                    || digits.len() > 6
+                    || !digits.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    return Err(invalid);
+                }
+                let c = match char::from_u32(u32::from_str_radix(digits, 16).unwrap()) {
+                    Some(c) => c,
+                    None => return Err(invalid),
+                };
+                self.pos += len + 2; // "{N...}"
+                c
+            }
+            Some(c) => {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                return Err(format!(
+                    "Lexer error: Unknown escape sequence \\{} at line {}",
+                    c, self.line
+                ));
+            }
+            None => {
+                return Err(format!(
+                    "Lexer error: Unexpected end of input after \\ at line {}",
+                    self.line
+                ))
+            }
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    pub fn lex(&mut self) -> Result<(), String> {
         while self.pos < self.txt.len() {
             let start_pos = self.pos;
 
+            // line comments
+            if self.txt[self.pos..].starts_with("//") {
+                match self.txt[self.pos..].find('\n') {
+                    Some(len) => self.pos += len,
+                    None => self.pos = self.txt.len(),
+                }
+                continue;
+            }
+
+            // block comments
+            if self.txt[self.pos..].starts_with("/*") {
+                let len = match self.txt[self.pos + 2..].find("*/") {
+                    Some(len) => len + 4,
+                    None => {
+                        return Err(format!(
+                            "Lexer error: Unterminated block comment starting at line {}",
+                            self.line
+                        ))
+                    }
+                };
+                let comment = &self.txt[self.pos..self.pos + len];
+                self.line += comment.matches('\n').count() as i32;
+                self.pos += len;
+                continue;
+            }
+
             // skip whitespace
             if is_whitespace(self.next_char()) {
                 if self.next_char() == '\n' {
@@ -127,26 +380,38 @@ impl Lexer {
             // string literals
             if self.next_char() == '"' {
                 self.pos += 1;
+                let start_line = self.line;
+                let mut s = String::new();
 
                 // whether double quotation is found
                 let mut dq_found = false;
                 while self.pos < self.txt.len() {
-                    if self.next_char() == '"' {
-                        dq_found = true;
-                        self.pos += 1;
-                        break;
+                    match self.next_char() {
+                        '"' => {
+                            dq_found = true;
+                            self.pos += 1;
+                            break;
+                        }
+                        '\\' => s.push(self.lex_escape()?),
+                        c => {
+                            if c == '\n' {
+                                self.line += 1;
+                            }
+                            s.push(c);
+                            self.pos += c.len_utf8();
+                        }
                     }
-                    self.pos += 1;
                 }
                 if !dq_found {
-                    error_exit(String::from("Lexer error: Unmatched '\"'"));
+                    return Err(format!(
+                        "Lexer error: Unmatched '\"' at line {}",
+                        start_line
+                    ));
                 }
-                let mut s = self.txt[start_pos + 1..self.pos - 1].to_string();
-                s = s.replace("\\n", "\n");
                 self.tokens.push(Token::new_with_line_num(
                     s,
                     TokenType::StrLiteral,
-                    self.line,
+                    start_line,
                 ));
                 continue;
             }
@@ -155,29 +420,36 @@ impl Lexer {
             if is_one_char_symbol(self.next_char()) {
                 self.pos += 1;
                 tok_ty = TokenType::Simbol
-            } else if self.next_char().is_numeric() {
-                // TODO: check if the token is a numerical litaral
-                self.pos += 1;
-                while self.pos < self.txt.len() && self.next_char().is_numeric() {
-                    self.pos += 1;
-                }
-                let s = self.txt[start_pos..self.pos].to_string();
-                tok_ty = TokenType::NumLiteral(s.parse::<i32>().unwrap());
+            } else if self.next_char().is_ascii_digit() {
+                let val = self.lex_number()?;
+                self.tokens.push(Token::from_value(&val, Some(self.line)));
+                continue;
+            } else if self.next_char() == '\'' {
+                let n = self.lex_char()?;
+                self.tokens.push(Token::new_num(n, Some(self.line)));
+                continue;
             } else if self.next_char().is_alphabetic() {
-                self.pos += 1;
+                self.pos += self.next_char().len_utf8();
                 while self.pos < self.txt.len() && self.next_char().is_alphanumeric() {
-                    self.pos += 1;
+                    self.pos += self.next_char().len_utf8();
                 }
                 tok_ty = TokenType::Ident;
             } else if is_normal_symbol(self.next_char()) {
-                self.pos += 1;
-                while self.pos < self.txt.len() && is_normal_symbol(self.next_char()) {
-                    self.pos += 1;
+                // the longest symbol is taken
+                match MULTI_CHAR_SYMBOLS
+                    .iter()
+                    .find(|sym| self.txt[self.pos..].starts_with(*sym))
+                {
+                    Some(sym) => self.pos += sym.len(),
+                    None => self.pos += 1,
                 }
                 tok_ty = TokenType::Simbol
             } else {
-                println!("Syntax error : '{}'", self.next_char());
-                std::process::exit(0);
+                return Err(format!(
+                    "Syntax error : '{}' at line {}",
+                    self.next_char(),
+                    self.line
+                ));
             }
             let s = self.txt[start_pos..self.pos].to_string();
             self.tokens
@@ -189,6 +461,7 @@ impl Lexer {
             .push(Token::new(String::from(""), TokenType::StrLiteral));
         self.tokens
             .push(Token::new(String::from(""), TokenType::StrLiteral));
+        Ok(())
     }
 }
 
@@ -200,7 +473,7 @@ mod lexer_tests {
     fn test_lexer() {
         let src = String::from("v200 = 200; if(v200 / 4 == 900) goto end;");
         let mut lexer = Lexer::new(src);
-        lexer.lex();
+        lexer.lex().unwrap();
         let mut tok_strs = Vec::new();
         for tok in lexer.tokens {
             tok_strs.push(tok.string);
@@ -213,4 +486,202 @@ mod lexer_tests {
             ]
         );
     }
+
+    #[test]
+    fn test_non_ascii_identifiers() {
+        let mut lexer = Lexer::new(String::from("été = 1; π2 = été;"));
+        lexer.lex().unwrap();
+        let tok_strs: Vec<String> = lexer.tokens.into_iter().map(|tok| tok.string).collect();
+        assert_eq!(
+            tok_strs,
+            vec!["été", "=", "1", ";", "π2", "=", "été", ";", "", "", ""]
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let src = String::from("a=-1; b==!c&&d||e");
+        let mut lexer = Lexer::new(src);
+        lexer.lex().unwrap();
+        let mut tok_strs = Vec::new();
+        for tok in lexer.tokens {
+            tok_strs.push(tok.string);
+        }
+        assert_eq!(
+            tok_strs,
+            vec!["a", "=", "-", "1", ";", "b", "==", "!", "c", "&&", "d", "||", "e", "", "", ""]
+        );
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let src = String::from("1_000 0x1F 0XfF 0b1010 007 'a' '\\n' '\\'' 2147483647");
+        let mut lexer = Lexer::new(src);
+        lexer.lex().unwrap();
+        let mut nums = Vec::new();
+        for tok in lexer.tokens {
+            if let TokenType::NumLiteral(n) = tok.ty {
+                nums.push(n);
+            }
+        }
+        assert_eq!(nums, vec![1000, 31, 255, 10, 7, 97, 10, 39, 2147483647]);
+    }
+
+    #[test]
+    fn test_number_literals_in_i64() {
+        let src = String::from("2147483648 0xFFFFFFFF 9223372036854775807");
+        let mut lexer = Lexer::new(src.clone());
+        assert!(lexer.lex().is_err());
+        let mut lexer = Lexer::new(src);
+        lexer.int_width = IntWidth::I64;
+        lexer.lex().unwrap();
+        let mut nums = Vec::new();
+        for tok in lexer.tokens {
+            if let TokenType::NumLiteral(n) = tok.ty {
+                nums.push(n);
+            }
+        }
+        assert_eq!(nums, vec![2147483648, 4294967295, i64::MAX]);
+    }
+
+    #[test]
+    fn test_float_literals() {
+        let src = String::from("2.25 1_000.5 6.02e23 1.5E-3 2.0e+2 1.x");
+        let mut lexer = Lexer::new(src);
+        lexer.lex().unwrap();
+        let mut floats = Vec::new();
+        for tok in lexer.tokens {
+            if let TokenType::FloatLiteral(bits) = tok.ty {
+                floats.push(f64::from_bits(bits));
+            }
+        }
+        assert_eq!(floats, vec![2.25, 1000.5, 6.02e23, 1.5e-3, 200.0]);
+    }
+
+    #[test]
+    fn test_invalid_number_literals() {
+        let cases = [
+            (
+                "a = 1;\nb = 99999999999;",
+                "Lexer error: Number literal 99999999999 is out of range at line 2",
+            ),
+            ("0x", "Lexer error: Invalid number literal 0x at line 1"),
+            ("1_", "Lexer error: Invalid number literal 1_ at line 1"),
+            (
+                "0b102",
+                "Lexer error: Invalid number literal 0b102 at line 1",
+            ),
+            ("12ab", "Lexer error: Invalid number literal 12ab at line 1"),
+            ("1.5e", "Lexer error: Invalid number literal 1.5e at line 1"),
+            ("1_.5", "Lexer error: Invalid number literal 1_.5 at line 1"),
+            (
+                "1.0e999",
+                "Lexer error: Number literal 1.0e999 is out of range at line 1",
+            ),
+            ("'ab'", "Lexer error: Invalid character literal at line 1"),
+            ("''", "Lexer error: Invalid character literal at line 1"),
+            (
+                "a = 1é;",
+                "Lexer error: Invalid number literal 1é at line 1",
+            ),
+            (
+                "a = 1.5é;",
+                "Lexer error: Invalid number literal 1.5é at line 1",
+            ),
+        ];
+        for (src, err) in cases.iter() {
+            let mut lexer = Lexer::new(src.to_string());
+            assert_eq!(lexer.lex(), Err(err.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let src = String::from("a = 1; // comment\n/* multi-line\ncomment */ b = a / 2; /**/\nc");
+        let mut lexer = Lexer::new(src);
+        lexer.lex().unwrap();
+        let mut toks = Vec::new();
+        for tok in lexer.tokens {
+            toks.push((tok.string, tok.line));
+        }
+        assert_eq!(
+            toks[4..],
+            [
+                (String::from("b"), Some(3)),
+                (String::from("="), Some(3)),
+                (String::from("a"), Some(3)),
+                (String::from("/"), Some(3)),
+                (String::from("2"), Some(3)),
+                (String::from(";"), Some(3)),
+                (String::from("c"), Some(4)),
+                (String::from(""), None),
+                (String::from(""), None),
+                (String::from(""), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let src = String::from("a = 1;\n/* comment");
+        let mut lexer = Lexer::new(src);
+        assert_eq!(
+            lexer.lex(),
+            Err(String::from(
+                "Lexer error: Unterminated block comment starting at line 2"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let src =
+            String::from("\"a\\tb\\\\c\\\"d\\x41\\u{3042}\\u{1F600}\" \"x\ny\" \"日本語\\n\" z");
+        let mut lexer = Lexer::new(src);
+        lexer.lex().unwrap();
+        let mut toks = Vec::new();
+        for tok in lexer.tokens {
+            toks.push((tok.string, tok.line));
+        }
+        assert_eq!(
+            toks[..4],
+            [
+                (String::from("a\tb\\c\"dA\u{3042}\u{1F600}"), Some(1)),
+                (String::from("x\ny"), Some(1)),
+                (String::from("日本語\n"), Some(2)),
+                (String::from("z"), Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_string_escapes() {
+        let cases = [
+            (
+                "\"abc\\q\"",
+                "Lexer error: Unknown escape sequence \\q at line 1",
+            ),
+            (
+                "\"\\x4\"",
+                "Lexer error: \\x must be followed by 2 hexadecimal digits at line 1",
+            ),
+            (
+                "\"\\u{110000}\"",
+                "Lexer error: Invalid unicode escape sequence at line 1",
+            ),
+            (
+                "\"\\u{}\"",
+                "Lexer error: Invalid unicode escape sequence at line 1",
+            ),
+            (
+                "\"\\u0041\"",
+                "Lexer error: Invalid unicode escape sequence at line 1",
+            ),
+            ("a;\n\"abc\\\"", "Lexer error: Unmatched '\"' at line 2"),
+        ];
+        for (src, err) in cases.iter() {
+            let mut lexer = Lexer::new(src.to_string());
+            assert_eq!(lexer.lex(), Err(err.to_string()));
+        }
+    }
 }
@@ -1,8 +1,12 @@
+mod bigint;
 mod error;
 mod exec;
+mod format;
 mod lexer;
 mod optimize;
 mod parser;
+mod type_check;
+mod value;
 mod var_map;
 
 extern crate libc;
@@ -12,15 +16,18 @@ use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use std::str;
+use value::IntWidth;
 use var_map::VariableMap;
 
 const VERSION_STR: &str = env!("CARGO_PKG_VERSION");
 
 #[derive(Debug)]
 pub struct Options {
-    emit_ir: bool,  /* whether a IR is printed or not */
-    exec: bool,     /* whether a program is executed or not */
-    optimize: bool, /* whether optimizer is enabled or not */
+    emit_ir: bool,       /* whether a IR is printed or not */
+    exec: bool,          /* whether a program is executed or not */
+    optimize: bool,      /* whether optimizer is enabled or not */
+    int_width: IntWidth, /* the width of integers */
+    strict_types: bool,  /* whether types are checked before execution */
 }
 
 impl Options {
@@ -29,6 +36,8 @@ impl Options {
             emit_ir: false,
             exec: true,
             optimize: true,
+            int_width: IntWidth::I32,
+            strict_types: false,
         }
     }
 }
@@ -39,6 +48,12 @@ pub fn run(s: String, opts: &Options, var_map: &mut VariableMap, is_interactive:
         println!("{}", e);
         return;
     }
+    if opts.strict_types {
+        if let Err(e) = parser.check_types(var_map) {
+            println!("{}", e);
+            return;
+        }
+    }
     if opts.emit_ir && opts.optimize {
         println!("Optimizing...");
     }
@@ -81,6 +96,8 @@ fn main() {
         println!("    -emit-ir          Display the intermidiate representation");
         println!("    -no-optimize      Doesn't optimize the program");
         println!("    -no-exec          Doesn't execute the program");
+        println!("    -int=32|64|big    Set the width of integers (default: 32)");
+        println!("    -strict-types     Check types before execution");
         return;
     }
 
@@ -95,6 +112,18 @@ fn main() {
             "-no-optimize" => {
                 options.optimize = false;
             }
+            "-int=32" | "--int=32" => {
+                options.int_width = IntWidth::I32;
+            }
+            "-int=64" | "--int=64" => {
+                options.int_width = IntWidth::I64;
+            }
+            "-int=big" | "--int=big" => {
+                options.int_width = IntWidth::Big;
+            }
+            "-strict-types" | "--strict-types" => {
+                options.strict_types = true;
+            }
             _ => {
                 if arg.starts_with("-") {
                     println!("Invalid option: {}", arg);
@@ -106,10 +135,11 @@ fn main() {
     }
 
     let mut var = VariableMap::new();
+    var.int_width = options.int_width;
 
     // run the file
-    if filepath != None {
-        let src = load_text(filepath.unwrap());
+    if let Some(filepath) = filepath {
+        let src = load_text(filepath);
         run(src, &options, &mut var, false);
     }
     // run in interactive mode
@@ -146,6 +176,7 @@ mod test {
     use super::*;
     use crate::lexer::Token;
     use crate::optimize;
+    use crate::value::Value;
 
     #[test]
     fn test_add() {
@@ -165,6 +196,31 @@ mod test {
         assert_eq!(result, 20);
     }
 
+    #[test]
+    fn test_relational() {
+        let src = String::from(
+            "a = 3; b = 5; c = (b > a) + (a >= 3) * 2 + (a > b) * 4 + (3 > 2 > 1) * 8;",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let c = var.get(&Token::new(String::from("c"), lexer::TokenType::Ident));
+        assert_eq!(c, 3);
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let src = String::from(
+            "a = 17 % 5; b = 6 & 3 | 8 ^ 1; c = ~5; d = 1 << 4 + 1; e = -16 >> 2; f = 10; f %= 4; f <<= 3; f |= 1;",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let mut vals = Vec::new();
+        for name in ["a", "b", "c", "d", "e", "f"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![2, 11, -6, 32, -4, 17]);
+    }
+
     #[test]
     fn test_int_var() {
         let src = String::from("result = 1; result = result + result * 2; result = result + 4;");
@@ -181,6 +237,36 @@ mod test {
         run(src, &Options::new(), &mut var, false);
         let result = var.get(&Token::new(String::from("result"), lexer::TokenType::Ident));
         assert_eq!(result, 12);
+
+        // each function has its own labels
+        let src = String::from(
+            "func f() { goto L; L: return 1; } func g() { L: return 2; } x = f() * 10 + g();",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let x = var.get(&Token::new(String::from("x"), lexer::TokenType::Ident));
+        assert_eq!(x, 12);
+        let errors = [
+            ("L: x = 1; L: x = 2;", "Duplicate label: L"),
+            (
+                "func f() { goto L; } L: x = 1; f();",
+                "Cannot jump to label L in another function",
+            ),
+            (
+                "goto L; func f() { L: return 1; }",
+                "Cannot jump to label L in another function",
+            ),
+            (
+                "func f() { if (1) goto L; } func g() { L: return 1; }",
+                "Cannot jump to label L in another function",
+            ),
+            ("goto M;", "Undefined label: M"),
+        ];
+        for (src, msg) in errors.iter() {
+            let mut parser = Parser::new(src.to_string());
+            let result = parser.compile(&mut VariableMap::new(), false);
+            assert_eq!(result, Err(msg.to_string()), "{}", src);
+        }
     }
 
     #[test]
@@ -203,19 +289,716 @@ mod test {
         assert_eq!(sum, 55);
     }
 
+    #[test]
+    fn test_while() {
+        let src = String::from("sum = 0; i = 0; while (i <= 10) { sum += i; i += 1; }");
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let sum = var.get(&Token::new(String::from("sum"), lexer::TokenType::Ident));
+        assert_eq!(sum, 55);
+    }
+
+    #[test]
+    fn test_do_while() {
+        let src =
+            String::from("n = 0; do { n += 1; } while (0); i = 0; do { i += 2; } while (i < 7);");
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let n = var.get(&Token::new(String::from("n"), lexer::TokenType::Ident));
+        assert_eq!(n, 1);
+        let i = var.get(&Token::new(String::from("i"), lexer::TokenType::Ident));
+        assert_eq!(i, 8);
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let src = String::from(
+            "sum = 0; for (i = 0; i < 10; i += 1) { if (i == 2) { continue; } if (i == 5) { break; } sum += i; }",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let sum = var.get(&Token::new(String::from("sum"), lexer::TokenType::Ident));
+        assert_eq!(sum, 8);
+    }
+
+    #[test]
+    fn test_labeled_break() {
+        let src = String::from(
+            "n = 0; outer: for (i = 0; i < 3; i += 1) { j = 0; while (1) { j += 1; if (j == 2) { continue outer; } if (i == 2) { break outer; } n += 1; } }",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let n = var.get(&Token::new(String::from("n"), lexer::TokenType::Ident));
+        assert_eq!(n, 2);
+        let i = var.get(&Token::new(String::from("i"), lexer::TokenType::Ident));
+        assert_eq!(i, 2);
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let src = String::from("if (1) { break; }");
+        let mut var = VariableMap::new();
+        let mut parser = Parser::new(src);
+        assert!(parser.compile(&mut var, false).is_err());
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let src = String::from(
+            "i = 100; sum = 0; for (var i = 0; i < 3; i += 1) { var j = i; if (1) { var j = 10; sum += j; } sum += j; }",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let sum = var.get(&Token::new(String::from("sum"), lexer::TokenType::Ident));
+        assert_eq!(sum, 33);
+        let i = var.get(&Token::new(String::from("i"), lexer::TokenType::Ident));
+        assert_eq!(i, 100);
+        let j = var.get(&Token::new(String::from("j"), lexer::TokenType::Ident));
+        assert_eq!(j, 0);
+    }
+
+    #[test]
+    fn test_redeclaration() {
+        let src = String::from("if (1) { var a = 1; var a = 2; }");
+        let mut var = VariableMap::new();
+        let mut parser = Parser::new(src);
+        assert!(parser.compile(&mut var, false).is_err());
+    }
+
     #[test]
     fn test_array() {
         let src = String::from("let a[3]; a[1] = 1; a[2] = 2;");
         let mut var = VariableMap::new();
         run(src, &Options::new(), &mut var, false);
         let a = [
-            var.array_get(&Token::new("a".to_string(), lexer::TokenType::Ident), 0),
-            var.array_get(&Token::new("a".to_string(), lexer::TokenType::Ident), 1),
-            var.array_get(&Token::new("a".to_string(), lexer::TokenType::Ident), 2),
+            var.array_get(&Token::new("a".to_string(), lexer::TokenType::Ident), &[0]),
+            var.array_get(&Token::new("a".to_string(), lexer::TokenType::Ident), &[1]),
+            var.array_get(&Token::new("a".to_string(), lexer::TokenType::Ident), &[2]),
         ];
         assert_eq!(a, [0, 1, 2]);
     }
 
+    #[test]
+    fn test_array_initializer() {
+        let src = String::from(
+            "let p[] = {2, 3, 5, 7}; let a[3] = 1; let m[2][2] = -1; let b[4] = {9, 8}; x = p[3] + a[2] + m[1][1] + b[1] + b[3];",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let x = var.get(&Token::new(String::from("x"), lexer::TokenType::Ident));
+        assert_eq!(x, 15);
+        for src in [
+            "let a[];",
+            "let a[2] = {1, 2, 3};",
+            "const N = 1; let a[N] = {1, 2};",
+        ]
+        .iter()
+        {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_whole_array() {
+        let src = String::from(
+            "let a[] = {1, 2, 3}; b = a; b[0] = 9; let m[2][5]; x = len(a) * 10 + len(m); y = a == b; z = a != b; b[0] = 1; w = a == b;",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let a = Token::new("a".to_string(), lexer::TokenType::Ident);
+        assert_eq!(var.array_get(&a, &[0]), 1);
+        let mut vals = Vec::new();
+        for name in ["x", "y", "z", "w"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![32, 0, 1, 1]);
+        for src in [
+            "let a[2]; x = a + 1;",
+            "let a[2]; a = 1;",
+            "let a[2]; x = a == 1;",
+        ]
+        .iter()
+        {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_array_shadowing() {
+        let src = String::from(
+            "let a[3] = 4; func f(a) { return a + 1; } x = f(2); if (1) { var a = 5; y = a; } z = len(a);",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let mut vals = Vec::new();
+        for name in ["x", "y", "z"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![3, 5, 3]);
+        for src in [
+            "var a = 1; let a[2];",
+            "let a[2]; if (1) { var b = 1; b = a; }",
+        ]
+        .iter()
+        {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_array_compound_assignment() {
+        let src = String::from(
+            "let a[3] = 1; let b[2][2]; a[0] += 5; a[1] *= a[0] + 1; b[1][1] -= 3; x = a[2] = b[0][0] = 7; i = 0; a[i += 1] += 10; y = (a[0] /= 2) + 100;",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let a = Token::new("a".to_string(), lexer::TokenType::Ident);
+        let b = Token::new("b".to_string(), lexer::TokenType::Ident);
+        let elems = [
+            var.array_get(&a, &[0]),
+            var.array_get(&a, &[1]),
+            var.array_get(&a, &[2]),
+            var.array_get(&b, &[0, 0]),
+            var.array_get(&b, &[1, 1]),
+        ];
+        assert_eq!(elems, [3, 17, 7, 7, -3]);
+        let x = var.get(&Token::new(String::from("x"), lexer::TokenType::Ident));
+        let y = var.get(&Token::new(String::from("y"), lexer::TokenType::Ident));
+        assert_eq!((x, y), (7, 103));
+    }
+
+    #[test]
+    fn test_multi_dimensional_array() {
+        let src = String::from(
+            "let m[2][3]; for (i = 0; i < 2; i += 1) { for (j = 0; j < 3; j += 1) { m[i][j] = i * 3 + j; } } x = m[1][m[0][2]];",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let m = Token::new("m".to_string(), lexer::TokenType::Ident);
+        assert_eq!(var.array_get(&m, &[0, 1]), 1);
+        assert_eq!(var.array_get(&m, &[1, 0]), 3);
+        let x = var.get(&Token::new(String::from("x"), lexer::TokenType::Ident));
+        assert_eq!(x, 5);
+    }
+
+    #[test]
+    fn test_func() {
+        let src = String::from(
+            "func fib(n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); } n = 10; result = fib(n);",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let result = var.get(&Token::new(String::from("result"), lexer::TokenType::Ident));
+        assert_eq!(result, 55);
+        // local variables of functions do not clobber global variables
+        let n = var.get(&Token::new(String::from("n"), lexer::TokenType::Ident));
+        assert_eq!(n, 10);
+    }
+
+    #[test]
+    fn test_func_arity() {
+        let src = String::from("func add(a, b) { return a + b; } c = add(1);");
+        let mut var = VariableMap::new();
+        let mut parser = Parser::new(src);
+        assert!(parser.compile(&mut var, false).is_err());
+        // keywords cannot be the names of functions
+        for src in ["func print() { return 1; }", "func if() { }"].iter() {
+            let mut parser = Parser::new(src.to_string());
+            let result = parser.compile(&mut VariableMap::new(), false);
+            assert!(result.unwrap_err().starts_with("Cannot declare"), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_string() {
+        let src = String::from(
+            "name = \"hrb\"; msg = name + \"!\"; n = len(msg) + len(\"\"); a = \"abc\" < \"abd\"; b = msg == \"hrb!\"; func f(s) { return s + s; } c = f(name);",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let msg = var.get_value(&Token::new(String::from("msg"), lexer::TokenType::Ident));
+        assert_eq!(msg, value::Value::Str(String::from("hrb!")));
+        let c = var.get_value(&Token::new(String::from("c"), lexer::TokenType::Ident));
+        assert_eq!(c, value::Value::Str(String::from("hrbhrb")));
+        let mut vals = Vec::new();
+        for name in ["n", "a", "b"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![4, 1, 1]);
+    }
+
+    #[test]
+    fn test_print_args() {
+        let valid = [
+            "println \"a\", 1, \"b\";",
+            "println;",
+            "printf \"100%%\";",
+            "printf \"%s: %05d\\n\", \"x\", 1 + 2;",
+        ];
+        for src in valid.iter() {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_ok());
+        }
+        let invalid = [
+            "print 1,;",
+            "printf \"%d %d\", 1;",
+            "printf \"%y\", 1;",
+            "printf a, 1;",
+        ];
+        for src in invalid.iter() {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_conditional() {
+        let src = String::from(
+            "func max(a, b) { return a > b ? a : b; } x = max(3, 8) * 10 + max(9, -1); n = -5; y = n > 0 ? 1 : n < 0 ? -1 : 0; z = 1 ? 2 : 3;",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let mut vals = Vec::new();
+        for name in ["x", "y", "z"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![89, -1, 2]);
+    }
+
+    #[test]
+    fn test_switch() {
+        let src = String::from(
+            "func f(d) { switch (d) { case 0: return 10; case 1: case 2: return 20; case 4: return 40; default: return -1; } }
+            func g(d) { switch (d) { case -100: return 1; case 100: return 2; } return 0; }
+            x = f(0) + f(1) + f(2) + f(3) + f(4) + f(5);
+            y = g(-100) * 10 + g(100) * 100 + g(0);
+            n = 0; i = 0;
+            L: while (i < 10) {
+                i = i + 1;
+                switch (i % 3) {
+                case 0: continue;
+                case 1: switch (i) { case 7: break L; default: break; }
+                }
+                n = n + i;
+            }",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let mut vals = Vec::new();
+        for name in ["x", "y", "n", "i"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![88, 210, 1 + 2 + 4 + 5, 7]);
+    }
+
+    #[test]
+    fn test_switch_lowering() {
+        // dense cases are lowered to a jump table and sparse ones to comparisons
+        let cases = [
+            ("switch (x) { case 1: case 2: case 4: }", true),
+            ("switch (x) { case 1: case 2: }", false),
+            ("switch (x) { case 1: case 20: case 300: }", false),
+        ];
+        for (src, has_table) in cases.iter() {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_ok());
+            let found = parser
+                .internal_code
+                .iter()
+                .any(|op| matches!(op, parser::Operation::JumpTable(..)));
+            assert_eq!(found, *has_table);
+        }
+        // floats select the same case with a jump table and with comparisons
+        let src = "n = 0; m = 0; for (i = 0; i < 4; i += 1) { x = float(i); if (i == 3) { x = 2.5; } switch (x) { case 0: case 1: n += 1; break; case 2: n += 10; break; default: n += 100; } switch (x) { case 0: m += 1; break; case 2: m += 10; break; default: m += 100; } }";
+        for optimize in [true, false].iter() {
+            let mut opts = Options::new();
+            opts.optimize = *optimize;
+            let mut var = VariableMap::new();
+            run(src.to_string(), &opts, &mut var, false);
+            let n = var.get(&Token::new(String::from("n"), lexer::TokenType::Ident));
+            let m = var.get(&Token::new(String::from("m"), lexer::TokenType::Ident));
+            assert_eq!((n, m), (112, 211));
+        }
+        let invalid = [
+            "switch (x) { x = 1; case 1: }",
+            "switch (x) { case 1: case 1: }",
+            "switch (x) { default: default: }",
+            "switch (x) { case y: }",
+            "case 1: x = 1;",
+            "switch (x) { case 1: continue; }",
+        ];
+        for src in invalid.iter() {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_const() {
+        let src = String::from(
+            "const N = 5; const M = N * 2 + 1; const K = M > 10 ? M << 2 : 0; let a[N]; a[N - 1] = M;
+            func f(N) { return N + M; } x = f(100); y = K + len(a); z = a[4];
+            if (1) { var M = 3; w = M; }
+            switch (11) { case N: v = 1; case M: v = 2; }",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let mut vals = Vec::new();
+        for name in ["x", "y", "z", "w", "v"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![111, 49, 11, 3, 2]);
+        let invalid = [
+            "const N = 1; N = 2;",
+            "const N = 1; N += 2;",
+            "const N = 1; x = (N = 3);",
+            "const N = 1; const N = 2;",
+            "x = 1; const N = x;",
+            "const N = input();",
+            "const N = (x = 2);",
+            "let a[2]; const a = 1;",
+        ];
+        for src in invalid.iter() {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_int_width() {
+        let cases = [
+            (
+                IntWidth::I32,
+                "x = 2147483647; x += 1; y = 65536 * 65536; z = 1 << 31;",
+                [-2147483648, 0, -2147483648],
+            ),
+            (
+                IntWidth::I64,
+                "x = 2147483647; x += 1; y = 65536 * 65536; z = 9223372036854775807 + 1;",
+                [2147483648, 4294967296, i64::MIN],
+            ),
+        ];
+        // the results are the same with and without constant folding
+        for optimize in [true, false].iter() {
+            for (int_width, src, expected) in cases.iter() {
+                let mut opts = Options::new();
+                opts.optimize = *optimize;
+                let mut var = VariableMap::new();
+                var.int_width = *int_width;
+                run(src.to_string(), &opts, &mut var, false);
+                let mut vals = Vec::new();
+                for name in ["x", "y", "z"].iter() {
+                    vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+                }
+                assert_eq!(vals, expected.to_vec());
+            }
+        }
+    }
+
+    #[test]
+    fn test_big_int() {
+        let src = "f = 1; for (i = 1; i <= 25; i += 1) { f *= i; } q = f / 1000000007; r = f % 1000000007; x = 9223372036854775807 + 1; y = x * x - 1; c = y < x * x; d = -x - 1 == -9223372036854775809; e = (x - 1) * 1;";
+        let expected = [
+            ("f", "15511210043330985984000000"),
+            ("q", "15511209934752516"),
+            ("r", "440732388"),
+            ("x", "9223372036854775808"),
+            ("y", "85070591730234615865843651857942052863"),
+            ("c", "1"),
+            ("d", "1"),
+        ];
+        for optimize in [true, false].iter() {
+            let mut opts = Options::new();
+            opts.optimize = *optimize;
+            let mut var = VariableMap::new();
+            var.int_width = IntWidth::Big;
+            run(src.to_string(), &opts, &mut var, false);
+            for (name, val) in expected.iter() {
+                let tok = Token::new(name.to_string(), lexer::TokenType::Ident);
+                assert_eq!(var.get_value(&tok).to_string(), *val);
+            }
+            // results which fit in i64 are ordinary integers
+            let e = var.get(&Token::new(String::from("e"), lexer::TokenType::Ident));
+            assert_eq!(e, i64::MAX);
+        }
+
+        // overflowing constants are folded into big integer literals
+        let mut var = VariableMap::new();
+        var.int_width = IntWidth::Big;
+        let mut parser = Parser::new(String::from("x = 9223372036854775807; y = x * 2;"));
+        assert!(parser.compile(&mut var, false).is_ok());
+        parser.optimize_constant_folding(&mut var);
+        let folded = parser.internal_code.iter().any(|op| match op {
+            parser::Operation::Copy(dist, val) => {
+                dist.string == "y"
+                    && val.string == "18446744073709551614"
+                    && matches!(val.ty, lexer::TokenType::BigLiteral(_))
+            }
+            _ => false,
+        });
+        assert!(folded);
+    }
+
+    #[test]
+    fn test_float() {
+        let src = "const PI = 3.5 - 0.25; a = 7 / 2; b = 7 / 2.0; c = -7.5 % 2; d = int(-3.99); e = float(3) / 4; f = 1 < 1.5; g = 2 == 2.0; h = PI * 2; i = int(h) / 2;";
+        let expected = [
+            Value::Int(3),
+            Value::Float(3.5),
+            Value::Float(-1.5),
+            Value::Int(-3),
+            Value::Float(0.75),
+            Value::Int(1),
+            Value::Int(1),
+            Value::Float(6.5),
+            Value::Int(3),
+        ];
+        for optimize in [true, false].iter() {
+            let mut opts = Options::new();
+            opts.optimize = *optimize;
+            let mut var = VariableMap::new();
+            run(src.to_string(), &opts, &mut var, false);
+            let mut vals = Vec::new();
+            for name in ["a", "b", "c", "d", "e", "f", "g", "h", "i"].iter() {
+                vals.push(var.get_value(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+            }
+            assert_eq!(vals, expected.to_vec());
+        }
+        assert_eq!(Value::Float(2.0).to_string(), "2.0");
+        let invalid = [
+            "x = 1.5; switch (x) { case 1.5: }",
+            "func float(x) { return x; }",
+        ];
+        for src in invalid.iter() {
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut VariableMap::new(), false).is_err());
+        }
+    }
+
+    #[test]
+    fn test_strict_types() {
+        let check = |src: &str| {
+            let mut var = VariableMap::new();
+            let mut parser = Parser::new(src.to_string());
+            assert!(parser.compile(&mut var, false).is_ok());
+            parser.check_types(&mut var)
+        };
+        let valid = [
+            "a = 3; if (a == 3) { a = a * 2.5 > 1 ? 1 : 2; }",
+            "b = true; while (b && !false) { b = 1 > 2 || len(\"s\") != 1; }",
+            "for (i = 0; i < 3; i += 1) { s = \"x\" + \"y\"; t = s < \"z\"; }",
+            "func f(s) { return s + \"!\"; } func g(n) { if (n <= 1) { return 1; } return n * g(n - 1); } x = f(\"a\"); y = g(3) + 1;",
+            "let arr[2][2]; arr[1][0] = 5; b = arr == arr; x = float(arr[1][0]); y = int(x);",
+            "switch (3) { case 1: case 2: case 3: break; }",
+            "switch (2.0) { case 1: case 2: case 3: break; }",
+            "const D = true; const E = 1 < 2 && !D; if (D || E) { x = 1; }",
+        ];
+        for src in valid.iter() {
+            assert_eq!(check(src), Ok(()), "{}", src);
+        }
+        let invalid = [
+            (
+                "a = 1; if (a = 3) { }",
+                "expected bool but found int at line 1",
+            ),
+            (
+                "x = (1 < 2) + 1;",
+                "cannot apply + to bool and int at line 1",
+            ),
+            (
+                "x = 1;\nx = \"a\";",
+                "x is int but str is assigned at line 2",
+            ),
+            ("while (1) { }", "expected bool but found int at line 1"),
+            (
+                "x = true ? 1 : \"a\";",
+                "both sides of ?: must have the same type but found int and str at line 1",
+            ),
+            (
+                "func f(n) { return n; }\nx = f(1);\ny = f(\"a\");",
+                "parameter n of f is int but str is given at line 3",
+            ),
+            (
+                "func f(n) { if (n) { return 1; } return \"a\"; }",
+                "expected bool but found int at line 1",
+            ),
+            (
+                "func f() { return 1; return \"a\"; }",
+                "function f returns int but str is returned at line 1",
+            ),
+            (
+                "let a[2]; a[0] = \"s\";",
+                "expected int but found str at line 1",
+            ),
+            ("x = len(3);", "expected str but found int at line 1"),
+            (
+                "let a[2];\na[0] += 1.5;",
+                "elements of array a are int but float is assigned at line 2",
+            ),
+            (
+                "const D = true; x = D + 1;",
+                "cannot apply + to bool and int at line 1",
+            ),
+        ];
+        for (src, msg) in invalid.iter() {
+            assert_eq!(check(src), Err(format!("Type error: {}", msg)), "{}", src);
+        }
+
+        // the types of global variables are kept in interactive mode
+        let mut opts = Options::new();
+        opts.strict_types = true;
+        let mut var = VariableMap::new();
+        run(String::from("x = \"a\";"), &opts, &mut var, true);
+        run(String::from("x = 1;"), &opts, &mut var, true);
+        let x = var.get_value(&Token::new(String::from("x"), lexer::TokenType::Ident));
+        assert_eq!(x, Value::Str(String::from("a")));
+        // old programs run without --strict-types
+        run(
+            String::from("x = 1; if (x) { x = (x > 0) + 1; }"),
+            &Options::new(),
+            &mut var,
+            true,
+        );
+        let x = var.get(&Token::new(String::from("x"), lexer::TokenType::Ident));
+        assert_eq!(x, 2);
+    }
+
+    #[test]
+    fn test_struct() {
+        let src = "let p: Point = {1, 2}; p.x += 10; q = p; q.y = 5; let p: Point = {p.y, p.x}; let ps[3]: Point; for (i = 0; i < len(ps); i += 1) { ps[i].x = i; ps[i].y = f(ps[i].x); } qs = ps; qs[2].y = 0; a = ps[2].y; b = qs[2].y; struct Point { x; y; } func f(n) { let t: Point = {n, n * n}; return t.x + t.y; }";
+        let expected = [
+            ("p.x", 2),
+            ("p.y", 11),
+            ("q.x", 11),
+            ("q.y", 5),
+            ("a", 6),
+            ("b", 0),
+        ];
+        for optimize in [true, false].iter() {
+            let mut opts = Options::new();
+            opts.optimize = *optimize;
+            let mut var = VariableMap::new();
+            run(src.to_string(), &opts, &mut var, false);
+            for (name, val) in expected.iter() {
+                let tok = Token::new(name.to_string(), lexer::TokenType::Ident);
+                assert_eq!(var.get(&tok), *val, "{}", name);
+            }
+        }
+
+        // structs are kept in interactive mode
+        let mut var = VariableMap::new();
+        run(
+            String::from("struct P { x; }"),
+            &Options::new(),
+            &mut var,
+            true,
+        );
+        run(
+            String::from("let p: P = {3};"),
+            &Options::new(),
+            &mut var,
+            true,
+        );
+        run(String::from("y = p.x;"), &Options::new(), &mut var, true);
+        let y = var.get(&Token::new(String::from("y"), lexer::TokenType::Ident));
+        assert_eq!(y, 3);
+
+        // parameters and variables declared by "var" hide structs
+        let src = "struct P { x; } let p: P = {4}; let ps[2]: P; func f(p, ps) { return p + ps; } a = f(1, 2); if (1) { var p = 5; var ps = 6; b = p * ps; } c = p.x;";
+        let mut var = VariableMap::new();
+        run(src.to_string(), &Options::new(), &mut var, false);
+        let mut vals = Vec::new();
+        for name in ["a", "b", "c"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![3, 30, 4]);
+
+        // a struct declared in a block hides the outer one
+        let src = "struct P { x; } let p: P = {1}; if (1) { let p: P = {p.x + 10}; q = p; a = p.x; } b = p.x; c = q.x;";
+        let mut var = VariableMap::new();
+        run(src.to_string(), &Options::new(), &mut var, false);
+        let mut vals = Vec::new();
+        for name in ["a", "b", "c"].iter() {
+            vals.push(var.get(&Token::new(name.to_string(), lexer::TokenType::Ident)));
+        }
+        assert_eq!(vals, vec![11, 1, 11]);
+
+        // a name is declared again only as the same kind
+        let errors = [
+            (
+                "struct P { x; } let p: P; let p[2];",
+                "Redeclaration of p in the same block: it is already declared as struct",
+            ),
+            (
+                "struct P { x; } let p[2]; let p: P;",
+                "Redeclaration of p in the same block: it is already declared as array",
+            ),
+            (
+                "struct P { x; } let p[2]: P; let p: P;",
+                "Redeclaration of p in the same block: it is already declared as array of structs",
+            ),
+        ];
+        for (src, msg) in errors.iter() {
+            let mut parser = Parser::new(src.to_string());
+            let result = parser.compile(&mut VariableMap::new(), false);
+            assert_eq!(result, Err(msg.to_string()), "{}", src);
+        }
+
+        let invalid = [
+            "struct P { x; } let p: P; var p = 1;",
+            "struct P { x; } let p: P; x = p + 1;",
+            "struct P { x; } let p: P; p = 1;",
+            "struct P { x; } let p: P; x = p.y;",
+            "struct P { x; } let p: P = {1, 2};",
+            "struct P { x; } struct Q { x; } let p: P; let q: Q; q = p;",
+            "struct P { x; } let ps[2]: P; x = ps[0];",
+            "struct P { x; x; }",
+            "struct P { }",
+            "struct P { x; } struct P { y; }",
+            "let p: P;",
+            "x = 1; y = x.y;",
+            "if (1) { struct P { x; } }",
+        ];
+        for src in invalid.iter() {
+            let mut parser = Parser::new(src.to_string());
+            assert!(
+                parser.compile(&mut VariableMap::new(), false).is_err(),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn test_logical() {
+        let src = String::from(
+            "let cnt[1]; func f(x) { cnt[0] = cnt[0] + 1; return x; } a = f(0) && f(1); b = f(2) || f(3); c = !a && (b || f(4)); n = cnt[0];",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let a = var.get(&Token::new(String::from("a"), lexer::TokenType::Ident));
+        let b = var.get(&Token::new(String::from("b"), lexer::TokenType::Ident));
+        let c = var.get(&Token::new(String::from("c"), lexer::TokenType::Ident));
+        assert_eq!([a, b, c], [0, 1, 1]);
+        // the rhs is not evaluated when the lhs determines the result
+        let n = var.get(&Token::new(String::from("n"), lexer::TokenType::Ident));
+        assert_eq!(n, 2);
+    }
+
+    #[test]
+    fn test_constant_folding_on_branches() {
+        let src = String::from(
+            "c = 0; if (c) { x = 1; } y = x + 1; z = 1 && 0; if (z || 0) { y = 100; }",
+        );
+        let mut var = VariableMap::new();
+        run(src, &Options::new(), &mut var, false);
+        let y = var.get(&Token::new(String::from("y"), lexer::TokenType::Ident));
+        assert_eq!(y, 1);
+    }
+
     #[test]
     fn test_build_cfg() {
         let src = String::from("a = 3; b = a; A: b = 100; goto A;");
@@ -234,11 +1017,11 @@ mod test {
         let cfg = optimize::cfg::ic_to_cfg(&parser.internal_code, &mut var_map);
         let const_maps = cfg.constant_propagation();
         let mut c = HashMap::new();
-        c.insert(String::from("a"), Some(1));
+        c.insert(String::from("a"), Some(Value::Int(1)));
         assert_eq!(const_maps[0].outs, c);
-        c.insert(String::from("b"), Some(2));
+        c.insert(String::from("b"), Some(Value::Int(2)));
         assert_eq!(const_maps[1].outs, c);
-        c.insert(String::from("c"), Some(3));
+        c.insert(String::from("c"), Some(Value::Int(3)));
         assert_eq!(const_maps[2].outs, c);
         c.insert(String::from("c"), None);
         assert_eq!(const_maps[3].outs, c);
@@ -254,7 +1037,7 @@ mod test {
         let const_maps = cfg.constant_propagation();
         println!("{:?}", const_maps);
         let mut c = HashMap::new();
-        c.insert(String::from("i"), Some(0));
+        c.insert(String::from("i"), Some(Value::Int(0)));
         assert_eq!(const_maps[0].outs, c);
         c.insert(String::from("i"), None);
         c.insert(String::from("_tmp0"), None);
@@ -262,4 +1045,61 @@ mod test {
         assert_eq!(const_maps[2].outs, c);
         assert_eq!(const_maps[3].outs, c);
     }
+
+    #[test]
+    fn test_constant_propagation_of_input() {
+        let src = String::from("a = 1; a = input(); b = a + 1;");
+        let mut var_map = VariableMap::new();
+        let mut parser = Parser::new(src);
+        let _ = parser.compile(&mut var_map, false);
+        let cfg = optimize::cfg::ic_to_cfg(&parser.internal_code, &mut var_map);
+        let const_maps = cfg.constant_propagation();
+        let outs = &const_maps.last().unwrap().outs;
+        assert_eq!(outs.get("a"), Some(&None));
+        assert_eq!(outs.get("b"), Some(&None));
+    }
+
+    #[test]
+    fn test_constant_propagation_of_arrays() {
+        let src = String::from(
+            "func f() { p[0] = 0; return 0; } let p[] = {2, 3}; a = p[0]; p[i] = 4; b = p[1]; p[1] = 5; c = p[1]; f(); d = p[1];",
+        );
+        let mut var_map = VariableMap::new();
+        let mut parser = Parser::new(src);
+        let _ = parser.compile(&mut var_map, false);
+        let cfg = optimize::cfg::ic_to_cfg(&parser.internal_code, &mut var_map);
+        let const_maps = cfg.constant_propagation();
+        let outs = &const_maps.last().unwrap().outs;
+        assert_eq!(outs.get("a"), Some(&Some(Value::Int(2))));
+        assert_eq!(outs.get("b"), Some(&None));
+        assert_eq!(outs.get("c"), Some(&Some(Value::Int(5))));
+        assert_eq!(outs.get("d"), Some(&None));
+    }
+
+    #[test]
+    fn test_constant_propagation_of_conditional() {
+        let src = String::from("c = 1; x = c ? 2 : 3; y = x;");
+        let mut var_map = VariableMap::new();
+        let mut parser = Parser::new(src);
+        let _ = parser.compile(&mut var_map, false);
+        let cfg = optimize::cfg::ic_to_cfg(&parser.internal_code, &mut var_map);
+        let const_maps = cfg.constant_propagation();
+        let outs = &const_maps.last().unwrap().outs;
+        assert_eq!(outs.get("x"), Some(&Some(Value::Int(2))));
+        assert_eq!(outs.get("y"), Some(&Some(Value::Int(2))));
+    }
+
+    #[test]
+    fn test_constant_propagation_of_switch() {
+        let src = String::from(
+            "c = 2; switch (c) { case 1: x = 10; case 2: x = 20; case 3: x = 30; } y = x;",
+        );
+        let mut var_map = VariableMap::new();
+        let mut parser = Parser::new(src);
+        let _ = parser.compile(&mut var_map, false);
+        let cfg = optimize::cfg::ic_to_cfg(&parser.internal_code, &mut var_map);
+        let const_maps = cfg.constant_propagation();
+        let outs = &const_maps.last().unwrap().outs;
+        assert_eq!(outs.get("y"), Some(&Some(Value::Int(20))));
+    }
 }
@@ -1,34 +1,48 @@
-use crate::{parser::Operation, var_map::VariableMap};
+use crate::{parser::Operation, value::IntWidth, var_map::VariableMap};
 
 #[derive(Debug)]
 pub struct Cfg {
     pub succs: Vec<Vec<usize>>,
     pub preds: Vec<Vec<usize>>,
     pub nodes: Vec<Operation>,
+    // the width of integers in constant folding
+    pub int_width: IntWidth,
     //abs_stmts: Vec<AbsStmt>,
 }
 
 impl Cfg {
-    fn new(ops: Vec<Operation>) -> Self {
+    fn new(ops: Vec<Operation>, int_width: IntWidth) -> Self {
         Cfg {
             succs: vec![Vec::new(); ops.len() + 1], // plus 1 in case there is the label at the end
             preds: vec![Vec::new(); ops.len() + 1],
-            nodes: ops.clone(),
+            nodes: ops,
+            int_width,
             //abs_stmts: Vec::new(),
         }
     }
 }
 
-pub fn ic_to_cfg(ops: &Vec<Operation>, var_map: &mut VariableMap) -> Cfg {
-    let mut cfg = Cfg::new(ops.clone());
-    for i in 0..ops.len() {
-        //println!("{} {:?}", i, ops[i]);
-        if let Operation::Goto(ref label) = ops[i] {
+pub fn ic_to_cfg(ops: &[Operation], var_map: &mut VariableMap) -> Cfg {
+    let mut cfg = Cfg::new(ops.to_vec(), var_map.int_width);
+    for (i, op) in ops.iter().enumerate() {
+        //println!("{} {:?}", i, op);
+        if let Operation::Goto(ref label) = *op {
             let dist = var_map.label_get(label) as usize;
             cfg.succs[i].push(dist);
             cfg.preds[dist].push(i);
             continue;
-        } else if let Operation::IfGoto(_, ref label) = ops[i] {
+        } else if let Operation::JumpTable(_, _, ref labels, ref default) = *op {
+            // succs are in the order of labels and then default
+            for label in labels.iter().chain(std::iter::once(default)) {
+                let dist = var_map.label_get(label) as usize;
+                cfg.succs[i].push(dist);
+                cfg.preds[dist].push(i);
+            }
+            continue;
+        } else if let Operation::Ret(..) = *op {
+            // control returns to the caller
+            continue;
+        } else if let Operation::IfGoto(_, ref label) = *op {
             let dist = var_map.label_get(label) as usize;
             cfg.succs[i].push(dist);
             cfg.preds[dist].push(i);
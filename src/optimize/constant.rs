@@ -1,8 +1,11 @@
 use super::cfg::{self, Cfg};
 use crate::error::error_exit;
+use crate::exec::{jump_table_index, shift_amount};
 use crate::lexer::{Token, TokenType};
 use crate::parser::{Operation, Parser};
+use crate::value::{cmp_numbers, ArithOp, Value};
 use crate::var_map::VariableMap;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
 // constant variable map for one node
@@ -10,7 +13,7 @@ use std::collections::{HashMap, HashSet};
 pub struct ConstMap {
     // Some(_) : constant
     // None : there are mutiple reachinig definitions
-    pub outs: HashMap<String, Option<i32>>,
+    pub outs: HashMap<String, Option<Value>>,
 }
 
 impl ConstMap {
@@ -21,14 +24,16 @@ impl ConstMap {
     }
 }
 
-fn is_constant(ins: &HashMap<String, Option<i32>>, tok: &Token) -> bool {
+fn is_constant(ins: &HashMap<String, Option<Value>>, tok: &Token) -> bool {
     match tok.ty {
-        TokenType::NumLiteral(_) => true,
+        TokenType::NumLiteral(_) | TokenType::BigLiteral(_) | TokenType::FloatLiteral(_) => true,
         TokenType::Ident => match ins.get(&tok.string) {
             Some(Some(_)) => true,
             Some(None) => false,
             None => false,
         },
+        // strings are not folded
+        TokenType::StrLiteral => false,
         _ => panic!(),
     }
 }
@@ -37,25 +42,59 @@ fn is_constant(ins: &HashMap<String, Option<i32>>, tok: &Token) -> bool {
 // None : Not constant
 // Some(Some(_)) : Constant
 // Some(None) : there are multiple reaching defs
-fn get_constant_var(ins: &HashMap<String, Option<i32>>, tok: &Token) -> Option<i32> {
+// array elements whose indices are constant are tracked with keys like "a[1][2]"
+fn element_key(
+    ins: &HashMap<String, Option<Value>>,
+    name: &Token,
+    indices: &[Token],
+) -> Option<String> {
+    let mut key = name.string.clone();
+    for index in indices {
+        key += &format!("[{}]", get_constant_var(ins, index)?);
+    }
+    Some(key)
+}
+
+fn remove_elements(ins: &mut HashMap<String, Option<Value>>, name: &Token) {
+    let prefix = format!("{}[", name.string);
+    ins.retain(|k, _| !k.starts_with(&prefix));
+}
+
+fn get_constant_value(ins: &HashMap<String, Option<Value>>, tok: &Token) -> Option<Value> {
     match tok.ty {
-        TokenType::NumLiteral(n) => Some(n),
-        TokenType::Ident => match ins.get(&tok.string) {
-            Some(Some(n)) => Some(*n),
-            _ => None,
-        },
+        TokenType::NumLiteral(n) => Some(Value::Int(n)),
+        TokenType::BigLiteral(ref n) => Some(Value::Big(n.clone())),
+        TokenType::FloatLiteral(bits) => Some(Value::Float(f64::from_bits(bits))),
+        TokenType::Ident => ins.get(&tok.string).cloned().flatten(),
+        TokenType::StrLiteral => None,
         _ => panic!(),
     }
 }
 
+// constants which fit in i64
+fn get_constant_var(ins: &HashMap<String, Option<Value>>, tok: &Token) -> Option<i64> {
+    match get_constant_value(ins, tok) {
+        Some(Value::Int(n)) => Some(n),
+        _ => None,
+    }
+}
+
 impl Cfg {
     pub fn constant_propagation(&self) -> Vec<ConstMap> {
         // constant valiables information for each node(operation)
         let mut const_maps: Vec<ConstMap> = vec![ConstMap::new(); self.nodes.len()];
+        // whether OUTs of each node has been computed
+        let mut visited = vec![false; self.nodes.len() + 1];
+        // successors of each node to which control can flow (updated when the node is visited)
+        let mut taken_succs: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
 
+        // start from the entry points of the program and functions
+        // nodes which are never visited are unreachable
         let mut worklist = HashSet::new();
         for i in 0..self.nodes.len() {
-            worklist.insert(i);
+            if i == 0 || self.preds[i].is_empty() {
+                worklist.insert(i);
+            }
         }
 
         while !worklist.is_empty() {
@@ -67,15 +106,20 @@ impl Cfg {
             }
             let op = &self.nodes[idx];
 
-            // INs = union n.out (n: predecessor of node)
+            // INs = union n.out (n: visited predecessor of node from which control can flow)
+            let preds: Vec<usize> = self.preds[idx]
+                .iter()
+                .copied()
+                .filter(|pred| visited[*pred] && taken_succs[*pred].contains(&idx))
+                .collect();
             let mut ins = HashMap::new();
-            for pred in &self.preds[idx] {
+            for pred in &preds {
                 for (k, v) in &const_maps[*pred].outs {
                     //println!("{:?} {:?}", k, v);
                     match ins.get(k) {
                         Some(Some(n)) => {
                             // overwrite with None
-                            if *v == None || *n != v.unwrap() {
+                            if v.as_ref() != Some(n) {
                                 ins.insert(k.clone(), None);
                             }
                         }
@@ -83,19 +127,26 @@ impl Cfg {
                             ins.insert(k.clone(), None);
                         }
                         None => {
-                            ins.insert(k.clone(), *v);
+                            ins.insert(k.clone(), v.clone());
                         }
                     }
                 }
             }
+            // variables which are not defined along some paths are not constant
+            for pred in &preds {
+                for (k, v) in ins.iter_mut() {
+                    if !const_maps[*pred].outs.contains_key(k) {
+                        *v = None;
+                    }
+                }
+            }
 
             // INs = f(INs)
             match op {
-                // TODO: support arrays
                 // x = a
-                &Operation::Copy(ref dist, ref operand) => {
+                Operation::Copy(ref dist, ref operand) => {
                     //let dist_val = get_constant_var(&ins, dist);
-                    let operand_val = get_constant_var(&ins, operand);
+                    let operand_val = get_constant_value(&ins, operand);
                     // when operand is a constant
                     if is_constant(&ins, operand) {
                         ins.insert(dist.string.clone(), Some(operand_val.unwrap()));
@@ -106,72 +157,181 @@ impl Cfg {
                     }
                 }
                 // binary
-                &Operation::Add(ref dist, ref operand1, ref operand2)
-                | &Operation::Sub(ref dist, ref operand1, ref operand2)
-                | &Operation::Mul(ref dist, ref operand1, ref operand2)
-                | &Operation::Div(ref dist, ref operand1, ref operand2)
-                | &Operation::Eq(ref dist, ref operand1, ref operand2)
-                | &Operation::Ne(ref dist, ref operand1, ref operand2)
-                | &Operation::Lt(ref dist, ref operand1, ref operand2)
-                | &Operation::Le(ref dist, ref operand1, ref operand2) => {
-                    let operand1_val = get_constant_var(&ins, operand1);
-                    let operand2_val = get_constant_var(&ins, operand2);
+                Operation::Add(ref dist, ref operand1, ref operand2)
+                | Operation::Sub(ref dist, ref operand1, ref operand2)
+                | Operation::Mul(ref dist, ref operand1, ref operand2)
+                | Operation::Div(ref dist, ref operand1, ref operand2)
+                | Operation::Mod(ref dist, ref operand1, ref operand2)
+                | Operation::BitAnd(ref dist, ref operand1, ref operand2)
+                | Operation::BitOr(ref dist, ref operand1, ref operand2)
+                | Operation::BitXor(ref dist, ref operand1, ref operand2)
+                | Operation::Shl(ref dist, ref operand1, ref operand2)
+                | Operation::Shr(ref dist, ref operand1, ref operand2)
+                | Operation::Eq(ref dist, ref operand1, ref operand2)
+                | Operation::Ne(ref dist, ref operand1, ref operand2)
+                | Operation::Lt(ref dist, ref operand1, ref operand2)
+                | Operation::Le(ref dist, ref operand1, ref operand2)
+                | Operation::Gt(ref dist, ref operand1, ref operand2)
+                | Operation::Ge(ref dist, ref operand1, ref operand2) => {
+                    let operand1_val = get_constant_value(&ins, operand1);
+                    let operand2_val = get_constant_value(&ins, operand2);
                     if is_constant(&ins, operand1) && is_constant(&ins, operand2) {
+                        let (lhs, rhs) = (operand1_val.unwrap(), operand2_val.unwrap());
+                        let int_width = self.int_width;
                         let ret = match &op {
-                            Operation::Add(..) => operand1_val.unwrap() + operand2_val.unwrap(),
-                            Operation::Sub(..) => operand1_val.unwrap() - operand2_val.unwrap(),
-                            Operation::Mul(..) => operand1_val.unwrap() * operand2_val.unwrap(),
+                            // overflows of floats are left to the runtime
+                            Operation::Add(..) => int_width.arith(ArithOp::Add, &lhs, &rhs).ok(),
+                            Operation::Sub(..) => int_width.arith(ArithOp::Sub, &lhs, &rhs).ok(),
+                            Operation::Mul(..) => int_width.arith(ArithOp::Mul, &lhs, &rhs).ok(),
                             Operation::Div(..) => {
-                                if operand2_val.unwrap() == 0 {
+                                if rhs.is_zero() {
                                     error_exit(String::from("Found divisionn by zero"));
                                 }
-                                operand1_val.unwrap() / operand2_val.unwrap()
-                            }
-                            Operation::Eq(..) => {
-                                if operand1_val.unwrap() == operand2_val.unwrap() {
-                                    1
-                                } else {
-                                    0
-                                }
-                            }
-                            Operation::Ne(..) => {
-                                if operand1_val.unwrap() != operand2_val.unwrap() {
-                                    1
-                                } else {
-                                    0
-                                }
+                                int_width.arith(ArithOp::Div, &lhs, &rhs).ok()
                             }
-                            Operation::Lt(..) => {
-                                if operand1_val.unwrap() < operand2_val.unwrap() {
-                                    1
-                                } else {
-                                    0
+                            Operation::Mod(..) => {
+                                if rhs.is_zero() {
+                                    error_exit(String::from("Found modulo by zero"));
                                 }
+                                int_width.arith(ArithOp::Mod, &lhs, &rhs).ok()
                             }
-                            Operation::Le(..) => {
-                                if operand1_val.unwrap() <= operand2_val.unwrap() {
-                                    1
-                                } else {
-                                    0
+                            Operation::Eq(..)
+                            | Operation::Ne(..)
+                            | Operation::Lt(..)
+                            | Operation::Le(..)
+                            | Operation::Gt(..)
+                            | Operation::Ge(..) => cmp_numbers(&lhs, &rhs).map(|ord| {
+                                let ret = match &op {
+                                    Operation::Eq(..) => ord == Ordering::Equal,
+                                    Operation::Ne(..) => ord != Ordering::Equal,
+                                    Operation::Lt(..) => ord == Ordering::Less,
+                                    Operation::Le(..) => ord != Ordering::Greater,
+                                    Operation::Gt(..) => ord == Ordering::Greater,
+                                    _ => ord != Ordering::Less,
+                                };
+                                Value::Int(ret as i64)
+                            }),
+                            // bit operations are folded only on integers in 64 bits
+                            _ => match (lhs, rhs) {
+                                (Value::Int(lhs), Value::Int(rhs)) => {
+                                    let ret = match &op {
+                                        Operation::BitAnd(..) => lhs & rhs,
+                                        Operation::BitOr(..) => lhs | rhs,
+                                        Operation::BitXor(..) => lhs ^ rhs,
+                                        Operation::Shl(..) => match shift_amount(rhs, int_width) {
+                                            Some(n) => lhs << n,
+                                            None => error_exit(format!(
+                                                "Shift amount out of range: {}",
+                                                rhs
+                                            )),
+                                        },
+                                        Operation::Shr(..) => match shift_amount(rhs, int_width) {
+                                            Some(n) => lhs >> n,
+                                            None => error_exit(format!(
+                                                "Shift amount out of range: {}",
+                                                rhs
+                                            )),
+                                        },
+                                        _ => panic!(),
+                                    };
+                                    Some(Value::Int(int_width.wrap(ret)))
                                 }
-                            }
-                            _ => panic!(),
+                                _ => None,
+                            },
                         };
-                        ins.insert(dist.string.clone(), Some(ret));
+                        ins.insert(dist.string.clone(), ret);
                     } else {
                         ins.insert(dist.string.clone(), None);
                     }
                 }
+                Operation::ArrayNew(ref name, ..) => remove_elements(&mut ins, name),
+                Operation::ArraySet(ref name, ref indices, ref val) => {
+                    match element_key(&ins, name, indices) {
+                        Some(key) => {
+                            let val = get_constant_value(&ins, val);
+                            ins.insert(key, val);
+                        }
+                        // any element may be changed
+                        None => remove_elements(&mut ins, name),
+                    }
+                }
+                // elements of the original are also known in the copy
+                Operation::ArrayCopy(ref dist, ref name) => {
+                    remove_elements(&mut ins, dist);
+                    let prefix = format!("{}[", name.string);
+                    let elems: Vec<(String, Option<Value>)> = ins
+                        .iter()
+                        .filter(|(k, _)| k.starts_with(&prefix))
+                        .map(|(k, v)| {
+                            (
+                                format!("{}{}", dist.string, &k[name.string.len()..]),
+                                v.clone(),
+                            )
+                        })
+                        .collect();
+                    ins.extend(elems);
+                }
+                Operation::ArrayGet(ref dist, ref name, ref indices) => {
+                    let val = element_key(&ins, name, indices)
+                        .and_then(|key| ins.get(&key).cloned().flatten());
+                    ins.insert(dist.string.clone(), val);
+                }
+                // functions may change any array
+                Operation::Call(ref dist, ..) => {
+                    ins.retain(|k, _| !k.contains('['));
+                    ins.insert(dist.string.clone(), None);
+                }
+                // conversions which fail are left to the runtime
+                Operation::ToInt(ref dist, ref val) => {
+                    let val = get_constant_value(&ins, val)
+                        .and_then(|val| self.int_width.to_int(&val).ok());
+                    ins.insert(dist.string.clone(), val);
+                }
+                Operation::ToFloat(ref dist, ref val) => {
+                    let val = get_constant_value(&ins, val).and_then(|val| val.to_float().ok());
+                    ins.insert(dist.string.clone(), val);
+                }
+                // the return values of builtin functions and inputs are unknown
+                Operation::Len(ref dist, _)
+                | Operation::ArrayLen(ref dist, _)
+                | Operation::ArrayEq(ref dist, ..)
+                | Operation::Input(ref dist)
+                | Operation::InputLine(ref dist)
+                | Operation::Eof(ref dist) => {
+                    ins.insert(dist.string.clone(), None);
+                }
                 _ => (),
             }
 
+            // conditional jumps whose conditions are constant have only one successor
+            // (succs of IfGoto are the destination and the next node)
+            let succs = match op {
+                Operation::IfGoto(ref cond, _) => match get_constant_var(&ins, cond) {
+                    Some(0) => &self.succs[idx][1..],
+                    Some(_) => &self.succs[idx][..1],
+                    None => &self.succs[idx][..],
+                },
+                // (succs of JumpTable are the labels and then the default label)
+                Operation::JumpTable(ref val, min, ref labels, _) => {
+                    match get_constant_var(&ins, val).map(|n| jump_table_index(n, *min, labels)) {
+                        Some(i) => &self.succs[idx][i..=i],
+                        None => &self.succs[idx][..],
+                    }
+                }
+                _ => &self.succs[idx][..],
+            };
+
+            taken_succs[idx] = succs.to_vec();
+
             // if f(INs) != OUTs then pushes all successors of the node into worklist
-            if ins != const_maps[idx].outs {
-                const_maps[idx].outs = ins;
-                for succ in &self.succs[idx] {
+            // successors are also pushed when the node is visited for the first time
+            if !visited[idx] || ins != const_maps[idx].outs {
+                visited[idx] = true;
+                for succ in succs {
                     //println!("push {}", succ);
                     worklist.insert(*succ);
                 }
+                const_maps[idx].outs = ins;
             }
         }
         const_maps
@@ -182,27 +342,53 @@ impl Parser {
     pub fn optimize_constant_folding(&mut self, var_map: &mut VariableMap) {
         let cfg = cfg::ic_to_cfg(&self.internal_code, var_map);
         let const_maps = cfg.constant_propagation();
-        for i in 0..self.internal_code.len() {
+        let len = self.internal_code.len();
+        for (i, const_map) in const_maps.iter().enumerate().take(len) {
             match &self.internal_code[i] {
-                Operation::Copy(ref dist, ref operand) => {
-                    if operand.ty == TokenType::Ident {
-                        if let Some(Some(ref n)) = const_maps[i].outs.get(&dist.string) {
-                            self.internal_code[i] =
-                                Operation::Copy(dist.clone(), Token::new_num(*n, None));
-                        }
+                Operation::Copy(ref dist, ref operand) if operand.ty == TokenType::Ident => {
+                    if let Some(Some(ref n)) = const_map.outs.get(&dist.string) {
+                        self.internal_code[i] =
+                            Operation::Copy(dist.clone(), Token::from_value(n, None));
                     }
                 }
-                Operation::Add(ref dist, ..)
+                Operation::ArrayGet(ref dist, ..)
+                | Operation::ToInt(ref dist, _)
+                | Operation::ToFloat(ref dist, _)
+                | Operation::Add(ref dist, ..)
                 | Operation::Sub(ref dist, ..)
                 | Operation::Mul(ref dist, ..)
                 | Operation::Div(ref dist, ..)
+                | Operation::Mod(ref dist, ..)
+                | Operation::BitAnd(ref dist, ..)
+                | Operation::BitOr(ref dist, ..)
+                | Operation::BitXor(ref dist, ..)
+                | Operation::Shl(ref dist, ..)
+                | Operation::Shr(ref dist, ..)
                 | Operation::Eq(ref dist, ..)
                 | Operation::Ne(ref dist, ..)
                 | Operation::Lt(ref dist, ..)
-                | Operation::Le(ref dist, ..) => {
-                    if let Some(Some(ref n)) = const_maps[i].outs.get(&dist.string) {
+                | Operation::Le(ref dist, ..)
+                | Operation::Gt(ref dist, ..)
+                | Operation::Ge(ref dist, ..) => {
+                    if let Some(Some(ref n)) = const_map.outs.get(&dist.string) {
                         self.internal_code[i] =
-                            Operation::Copy(dist.clone(), Token::new_num(*n, None));
+                            Operation::Copy(dist.clone(), Token::from_value(n, None));
+                    }
+                }
+                // conditional jumps whose conditions are constant
+                Operation::IfGoto(ref cond, ref label) => {
+                    match get_constant_var(&const_map.outs, cond) {
+                        Some(0) => self.internal_code[i] = Operation::Nop,
+                        Some(_) => self.internal_code[i] = Operation::Goto(label.clone()),
+                        None => (),
+                    }
+                }
+                Operation::JumpTable(ref val, min, ref labels, ref default) => {
+                    if let Some(n) = get_constant_var(&const_map.outs, val) {
+                        let label = labels
+                            .get(jump_table_index(n, *min, labels))
+                            .unwrap_or(default);
+                        self.internal_code[i] = Operation::Goto(label.clone());
                     }
                 }
                 _ => (),
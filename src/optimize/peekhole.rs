@@ -42,6 +42,16 @@ impl Parser {
                     self.internal_code[i] = Operation::IfGoto(cond.clone(), final_dist.clone());
                 }
             }
+            if let Operation::JumpTable(ref val, min, ref labels, ref default) =
+                self.internal_code[i]
+            {
+                let labels = labels
+                    .iter()
+                    .map(|label| self.get_dist(var_map, label, label).clone())
+                    .collect();
+                let default = self.get_dist(var_map, default, default).clone();
+                self.internal_code[i] = Operation::JumpTable(val.clone(), min, labels, default);
+            }
         }
     }
 
@@ -9,7 +9,7 @@ impl Parser {
                 self.internal_code.remove(pos as usize);
                 for label in var_map.label_map.clone().keys() {
                     let line = *var_map.label_map.get(label).unwrap();
-                    if line > pos as i32 {
+                    if line > pos {
                         var_map.label_map.insert(label.clone(), line - 1);
                     }
                 }
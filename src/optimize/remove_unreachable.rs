@@ -11,6 +11,10 @@ impl Parser {
 
         let mut worklist = HashSet::new();
         worklist.insert(0);
+        // function bodies are reachable via their entry points
+        for func in self.functions.values() {
+            worklist.insert(var_map.label_get(&func.label) as usize);
+        }
 
         while !worklist.is_empty() {
             let n: usize = *worklist.iter().next().unwrap();
@@ -24,9 +28,9 @@ impl Parser {
             }
         }
 
-        for i in 0..self.internal_code.len() {
-            if !is_reachable[i] {
-                self.internal_code[i] = Operation::Nop;
+        for (op, reachable) in self.internal_code.iter_mut().zip(&is_reachable) {
+            if !reachable {
+                *op = Operation::Nop;
             }
         }
         self.remove_nop(var_map);
@@ -1,7 +1,11 @@
+use crate::format;
 use crate::lexer;
 use crate::lexer::{Lexer, Token, TokenType};
+use crate::optimize::cfg;
+use crate::value::{IntWidth, Value};
 use crate::var_map::VariableMap;
-use std::collections::HashSet;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 pub enum Operation {
@@ -10,136 +14,277 @@ pub enum Operation {
     Sub(Token, Token, Token),
     Mul(Token, Token, Token),
     Div(Token, Token, Token),
+    Mod(Token, Token, Token),
+    BitAnd(Token, Token, Token),
+    BitOr(Token, Token, Token),
+    BitXor(Token, Token, Token),
+    Shl(Token, Token, Token),
+    Shr(Token, Token, Token),
     Eq(Token, Token, Token),
     Ne(Token, Token, Token),
     Lt(Token, Token, Token),
     Le(Token, Token, Token),
+    Gt(Token, Token, Token),
+    Ge(Token, Token, Token),
     Print(Token),
     Println(Token),
+    Printf(Token, Vec<Token>), // format, arguments
+    Len(Token, Token),         // dist, string
+    ToInt(Token, Token),       // dist, number
+    ToFloat(Token, Token),     // dist, number
+    Input(Token),              // dist (reads an integer)
+    InputLine(Token),          // dist (reads a line)
+    Eof(Token),                // dist (whether stdin reaches EOF)
     Time,
     Goto(Token),
-    IfGoto(Token, Token),          // cond, label
-    ArrayNew(Token, Token),        // name, size
-    ArraySet(Token, Token, Token), // name, index, val
-    ArrayGet(Token, Token, Token), // dist, name, index
+    IfGoto(Token, Token),                     // cond, label
+    JumpTable(Token, i64, Vec<Token>, Token), // value, min, labels (for min, min + 1, ...), default
+    ArrayNew(Token, Vec<Token>, Token),       // name, sizes, initial value
+    ArraySet(Token, Vec<Token>, Token),       // name, indices, val
+    ArrayGet(Token, Token, Vec<Token>),       // dist, name, indices
+    ArrayLen(Token, Token),                   // dist, name
+    ArrayCopy(Token, Token),                  // name of the copy, name of the original
+    ArrayEq(Token, Token, Token),             // dist, lhs name, rhs name
+    Call(Token, Token, Vec<Token>),           // dist, function name, arguments
+    Ret(Token),                               // return value
     Nop,
 }
 
-fn dump_operation(op: &Operation) {
+fn dump_operation(op: &Operation, int_width: IntWidth) {
+    let dump_token = |tok: &Token| lexer::dump_token(tok, int_width);
     match op {
         Operation::Copy(ref dist, ref operand) => {
-            println!(
-                "copy {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(operand)
-            );
+            println!("copy {}, {}", dump_token(dist), dump_token(operand));
         }
         Operation::Add(ref dist, ref lhs, ref rhs) => {
             println!(
                 "add {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(lhs),
-                lexer::dump_token(rhs)
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
             );
         }
         Operation::Sub(ref dist, ref lhs, ref rhs) => {
             println!(
                 "sub {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(lhs),
-                lexer::dump_token(rhs)
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
             );
         }
         Operation::Mul(ref dist, ref lhs, ref rhs) => {
             println!(
                 "mul {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(lhs),
-                lexer::dump_token(rhs)
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
             );
         }
         Operation::Div(ref dist, ref lhs, ref rhs) => {
             println!(
                 "div {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(lhs),
-                lexer::dump_token(rhs)
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::Mod(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "mod {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::BitAnd(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "and {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::BitOr(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "or {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::BitXor(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "xor {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::Shl(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "shl {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::Shr(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "shr {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
             );
         }
         Operation::Eq(ref dist, ref lhs, ref rhs) => {
             println!(
                 "eq {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(lhs),
-                lexer::dump_token(rhs)
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
             );
         }
         Operation::Ne(ref dist, ref lhs, ref rhs) => {
             println!(
                 "ne {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(lhs),
-                lexer::dump_token(rhs)
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
             );
         }
         Operation::Lt(ref dist, ref lhs, ref rhs) => {
             println!(
                 "lt {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(lhs),
-                lexer::dump_token(rhs)
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
             );
         }
         Operation::Le(ref dist, ref lhs, ref rhs) => {
             println!(
                 "le {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(lhs),
-                lexer::dump_token(rhs)
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::Gt(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "gt {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::Ge(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "ge {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
             );
         }
         Operation::Print(ref var) => {
-            println!("print {}", lexer::dump_token(var));
+            println!("print {}", dump_token(var));
         }
         Operation::Println(ref var) => {
-            println!("println {}", lexer::dump_token(var));
+            println!("println {}", dump_token(var));
+        }
+        Operation::Printf(ref fmt, ref args) => {
+            let mut operands = vec![dump_token(fmt)];
+            operands.extend(args.iter().map(dump_token));
+            println!("printf {}", operands.join(", "));
+        }
+        Operation::Len(ref dist, ref val) => {
+            println!("len {}, {}", dump_token(dist), dump_token(val));
+        }
+        Operation::ToInt(ref dist, ref val) => {
+            println!("toInt {}, {}", dump_token(dist), dump_token(val));
+        }
+        Operation::ToFloat(ref dist, ref val) => {
+            println!("toFloat {}, {}", dump_token(dist), dump_token(val));
+        }
+        Operation::Input(ref dist) => {
+            println!("input {}", dump_token(dist));
+        }
+        Operation::InputLine(ref dist) => {
+            println!("inputLine {}", dump_token(dist));
+        }
+        Operation::Eof(ref dist) => {
+            println!("eof {}", dump_token(dist));
         }
         Operation::Goto(ref label) => {
-            println!("goto {}", lexer::dump_token(label));
+            println!("goto {}", dump_token(label));
         }
         Operation::IfGoto(ref cond, ref label) => {
+            println!("ifGoto {}, {}", dump_token(cond), dump_token(label));
+        }
+        Operation::JumpTable(ref val, min, ref label_toks, ref default) => {
+            let labels: Vec<String> = label_toks.iter().map(dump_token).collect();
             println!(
-                "ifGoto {}, {}",
-                lexer::dump_token(cond),
-                lexer::dump_token(label)
+                "jumpTable {}, {}, [{}], {}",
+                dump_token(val),
+                min,
+                labels.join(", "),
+                dump_token(default)
             );
         }
         Operation::Time => {
             println!("time");
         }
-        Operation::ArrayNew(ref ident, ref size_tok) => {
+        Operation::ArrayNew(ref ident, ref size_toks, ref val_tok) => {
+            let sizes: Vec<String> = size_toks.iter().map(dump_token).collect();
             println!(
-                "arrayNew {}, {}",
-                lexer::dump_token(ident),
-                lexer::dump_token(size_tok)
+                "arrayNew {}, [{}], {}",
+                dump_token(ident),
+                sizes.join(", "),
+                dump_token(val_tok)
             );
         }
-        Operation::ArrayGet(ref dist, ref ident, ref index_tok) => {
+        Operation::ArrayGet(ref dist, ref ident, ref index_toks) => {
+            let indices: Vec<String> = index_toks.iter().map(dump_token).collect();
             println!(
-                "arrayGetElem {}, {}, {}",
-                lexer::dump_token(dist),
-                lexer::dump_token(ident),
-                lexer::dump_token(index_tok)
+                "arrayGetElem {}, {}, [{}]",
+                dump_token(dist),
+                dump_token(ident),
+                indices.join(", ")
             );
         }
-        Operation::ArraySet(ref ident, ref index_tok, ref val_tok) => {
+        Operation::ArraySet(ref ident, ref index_toks, ref val_tok) => {
+            let indices: Vec<String> = index_toks.iter().map(dump_token).collect();
             println!(
-                "arraySet {}, {}, {}",
-                lexer::dump_token(ident),
-                lexer::dump_token(index_tok),
-                lexer::dump_token(val_tok)
+                "arraySet {}, [{}], {}",
+                dump_token(ident),
+                indices.join(", "),
+                dump_token(val_tok)
             );
         }
+        Operation::ArrayLen(ref dist, ref ident) => {
+            println!("arrayLen {}, {}", dump_token(dist), dump_token(ident));
+        }
+        Operation::ArrayCopy(ref dist, ref ident) => {
+            println!("arrayCopy {}, {}", dump_token(dist), dump_token(ident));
+        }
+        Operation::ArrayEq(ref dist, ref lhs, ref rhs) => {
+            println!(
+                "arrayEq {}, {}, {}",
+                dump_token(dist),
+                dump_token(lhs),
+                dump_token(rhs)
+            );
+        }
+        Operation::Call(ref dist, ref name, ref args) => {
+            let args: Vec<String> = args.iter().map(dump_token).collect();
+            println!(
+                "call {}, {}({})",
+                dump_token(dist),
+                name.string,
+                args.join(", ")
+            );
+        }
+        Operation::Ret(ref val) => {
+            println!("ret {}", dump_token(val));
+        }
         Operation::Nop => {
             println!("nop");
         }
@@ -148,8 +293,73 @@ fn dump_operation(op: &Operation) {
 
 #[derive(PartialEq, Eq)]
 enum Block {
-    IfElse(Token, Option<Token>),           // L0, L1
-    For(Token, Token, Token, usize, usize), // L0, L1, L2, e1_start, e2_start
+    IfElse(Token, Option<Token>), // L0, L1
+    // the last parameter of loops is the name of the loop (see loop_labels)
+    For(Token, Token, Token, usize, usize, Option<Token>), // L0, L1, L2, e1_start, e2_start
+    While(Token, Token, Option<Token>),                    // L0, L1
+    DoWhile(Token, Token, Token, Option<Token>),           // L0, L1, L2
+    Func(Token),                                           // L0
+    // L0, L1, the value, (case value, label)s, the label of default (see compile_switch_end)
+    Switch(
+        Token,
+        Token,
+        Token,
+        Vec<(i64, Token)>,
+        Option<Token>,
+        Option<Token>,
+    ),
+}
+
+// information about an operation for the type checker
+// (valid until the optimizer changes internal_code)
+#[derive(Debug, Clone, Copy)]
+pub struct OpInfo {
+    // the line of the statement
+    pub line: Option<i32>,
+    // temporary variables are reused after they are reset (see reset_temp_vars)
+    pub temp_var_gen: usize,
+    // Eq(t, v, 0) or Ne(t, v, 0) which tests whether v is true
+    // (conditions, !, && and ||)
+    pub truth_test: bool,
+}
+
+// variables declared by "var" in a block
+struct Scope {
+    // the name in the source -> the name in the internal code
+    vars: HashMap<String, String>,
+    // constants declared by "const" and their values (literals)
+    consts: HashMap<String, Token>,
+    // names of arrays declared by "let" (arrays themselves are global)
+    arrays: HashSet<String>,
+    // variables and arrays of structs declared by "let" -> the names of the structs
+    struct_vars: HashMap<String, String>,
+    struct_arrays: HashMap<String, String>,
+    // what each "let" so far has declared (the maps above are filled in advance by the scans)
+    lets: HashMap<String, &'static str>,
+    // variables declared in a block scope are renamed to "name@id"
+    // so that they never conflict with variables of other scopes
+    id: Option<usize>,
+}
+
+impl Scope {
+    fn new(id: Option<usize>) -> Self {
+        Scope {
+            vars: HashMap::new(),
+            consts: HashMap::new(),
+            arrays: HashSet::new(),
+            struct_vars: HashMap::new(),
+            struct_arrays: HashMap::new(),
+            lets: HashMap::new(),
+            id,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Function {
+    // the label of the first operation of the function body
+    pub label: Token,
+    pub params: Vec<Token>,
 }
 
 // TODO: this is dirty. Is it better to make an expression parser?
@@ -167,32 +377,198 @@ pub struct Parser {
     // the number of variables which is used
     // to store temporay results of calculation
     temp_var_cnt: usize,
+    temp_var_gen: usize,
     temp_label_cnt: usize,
     blocks: Vec<Block>,
+    // user-defined functions collected by scan_functions
+    pub functions: HashMap<String, Function>,
+    // user-defined structs and their fields collected by scan_structs
+    structs: HashMap<String, Vec<String>>,
+    // the first one is the top level scope
+    scopes: Vec<Scope>,
+    scope_cnt: usize,
+    // the label just before the current statement
+    last_label: Option<Token>,
+    // labels and their positions, which are stored into VariableMap after compile
+    labels: Vec<(Token, usize)>,
+    // the entry label of the function being compiled
+    // labels written in a function are local to it (see user_label)
+    cur_func: Option<Token>,
+    // labels written in the source and the gotos to them (checked after compile)
+    user_labels: HashSet<String>,
+    gotos: Vec<(Token, Token)>, // the label in the source, the label in the internal code
+    // the line of the statement being compiled
+    cur_line: Option<i32>,
+    // the information of each operation in internal_code
+    pub op_info: Vec<OpInfo>,
+}
+
+// constructor of binary operations (dist, lhs, rhs)
+type BinaryOp = fn(Token, Token, Token) -> Operation;
+
+// compound assignment operators and the corresponding operations
+const COMPOUND_ASSIGNMENTS: [(&str, BinaryOp); 10] = [
+    ("+=", Operation::Add),
+    ("-=", Operation::Sub),
+    ("*=", Operation::Mul),
+    ("/=", Operation::Div),
+    ("%=", Operation::Mod),
+    ("&=", Operation::BitAnd),
+    ("|=", Operation::BitOr),
+    ("^=", Operation::BitXor),
+    ("<<=", Operation::Shl),
+    (">>=", Operation::Shr),
+];
+
+// switch statements with fewer cases are lowered to comparisons
+const JUMP_TABLE_MIN_CASES: usize = 3;
+
+// functions which are provided by the interpreter and their arities
+const BUILTIN_FUNCTIONS: [(&str, usize); 6] = [
+    ("len", 1),
+    ("input", 0),
+    ("inputLine", 0),
+    ("eof", 0),
+    ("int", 1),
+    ("float", 1),
+];
+
+// words which begin statements
+const KEYWORDS: [&str; 21] = [
+    "break", "case", "const", "continue", "default", "do", "else", "for", "func", "goto", "if",
+    "let", "print", "printf", "println", "return", "struct", "switch", "time", "var", "while",
+];
+
+// true and false are 1 and 0 of type bool
+fn is_bool_literal(tok: &Token) -> bool {
+    tok.ty == TokenType::Ident && (tok.string == "true" || tok.string == "false")
+}
+
+// names which cannot be declared as variables, constants, structs or functions
+fn is_reserved(tok: &Token) -> bool {
+    tok.ty != TokenType::Ident || is_bool_literal(tok) || KEYWORDS.contains(&tok.string.as_str())
+}
+
+// a field of a struct is stored in the variable (or the array) "name.field"
+// ("name.field@id" for a struct declared in a block scope)
+fn field_of(tok: &Token, field: &str) -> Token {
+    let mut ret = tok.clone();
+    ret.string = match tok.string.split_once('@') {
+        Some((name, id)) => format!("{}.{}@{}", name, field, id),
+        None => format!("{}.{}", tok.string, field),
+    };
+    ret
+}
+
+// the name of tok in the internal code if it is declared in scope
+fn rename_in(scope: &Scope, tok: &Token) -> Token {
+    let mut ret = tok.clone();
+    if let Some(id) = scope.id {
+        ret.string = format!("{}@{}", tok.string, id);
+    }
+    ret
+}
+
+// "b = a;" in the source as (b, a)
+// copies from or to parameters and variables declared by "var" are skipped
+// because they never refer to arrays (or structs)
+fn scan_copies(tokens: &[Token]) -> Vec<(String, String)> {
+    let mut ret = Vec::new();
+    // names declared by "var" or as parameters in each enclosing block
+    let mut shadowed: Vec<HashSet<&str>> = vec![HashSet::new()];
+    let mut params = HashSet::new();
+    for i in 0..tokens.len() - 3 {
+        if tokens[i].matches("{") {
+            shadowed.push(std::mem::take(&mut params));
+        } else if tokens[i].matches("}") && shadowed.len() > 1 {
+            shadowed.pop();
+        } else if tokens[i].matches("var") {
+            if let Some(names) = shadowed.last_mut() {
+                names.insert(tokens[i + 1].string.as_str());
+            }
+        } else if tokens[i].matches("func") {
+            params = tokens[i + 3..]
+                .iter()
+                .take_while(|tok| !tok.matches(")"))
+                .filter(|tok| tok.ty == TokenType::Ident)
+                .map(|tok| tok.string.as_str())
+                .collect();
+        } else if tokens[i].ty == TokenType::Ident
+            && tokens[i + 1].matches("=")
+            && tokens[i + 2].ty == TokenType::Ident
+            && tokens[i + 3].matches(";")
+        {
+            let (dist, src) = (&tokens[i].string, &tokens[i + 2].string);
+            if !shadowed
+                .iter()
+                .any(|names| names.contains(dist.as_str()) || names.contains(src.as_str()))
+            {
+                ret.push((dist.clone(), src.clone()));
+            }
+        }
+    }
+    ret
+}
+
+fn is_unary_operator(tok: &Token) -> bool {
+    tok.ty == TokenType::Simbol && matches!(tok.string.as_str(), "+" | "-" | "!" | "~")
+}
+
+// binary operators including assignments
+fn is_binary_operator(tok: &Token) -> bool {
+    tok.ty == TokenType::Simbol
+        && matches!(
+            tok.string.as_str(),
+            "+" | "-"
+                | "*"
+                | "/"
+                | "%"
+                | "<<"
+                | ">>"
+                | "&"
+                | "^"
+                | "|"
+                | "=="
+                | "!="
+                | "<"
+                | "<="
+                | ">"
+                | ">="
+                | "&&"
+                | "||"
+                | "?"
+                | ":"
+                | "="
+                | "+="
+                | "-="
+                | "*="
+                | "/="
+                | "%="
+                | "&="
+                | "|="
+                | "^="
+                | "<<="
+                | ">>="
+        )
 }
 
 macro_rules! parse_binary_op {
-    ($func_name:ident, $child:ident, $op1:expr, $path1:path, $op2:expr, $path2:path) => {
+    ($func_name:ident, $child:ident, $($op:expr => $path:path),+) => {
         fn $func_name(&mut self) -> Result<Token, String> {
             let mut ret = self.$child()?;
             while self.expr_pos < self.lexer.tokens.len() {
-                if self.lexer.tokens[self.expr_pos].matches($op1) {
-                    self.expr_pos += 1;
-                    let child = self.$child()?;
-                    let tmp = self.make_temp_var();
-                    let op = $path1(tmp.clone(), ret, child);
-                    self.push_internal_code(op);
-                    ret = tmp;
-                } else if self.lexer.tokens[self.expr_pos].matches($op2) {
-                    self.expr_pos += 1;
-                    let child = self.$child()?;
-                    let tmp = self.make_temp_var();
-                    let op = $path2(tmp.clone(), ret, child);
-                    self.push_internal_code(op);
-                    ret = tmp;
-                } else {
-                    break;
-                }
+                $(
+                    if self.lexer.tokens[self.expr_pos].matches($op) {
+                        self.expr_pos += 1;
+                        let child = self.$child()?;
+                        let tmp = self.make_temp_var();
+                        let op = $path(tmp.clone(), ret, child);
+                        self.push_internal_code(op);
+                        ret = tmp;
+                        continue;
+                    }
+                )+
+                break;
             }
             Ok(ret)
         }
@@ -201,8 +577,7 @@ macro_rules! parse_binary_op {
 
 impl Parser {
     pub fn new(s: String) -> Self {
-        let mut lexer = Lexer::new(s);
-        lexer.lex();
+        let lexer = Lexer::new(s);
         Parser {
             pos: 0,
             lexer,
@@ -211,11 +586,331 @@ impl Parser {
             cur_expr_param_start_pos: [0; 4],
             expr_pos: 0,
             temp_var_cnt: 0,
+            temp_var_gen: 0,
             temp_label_cnt: 0,
             blocks: Vec::new(),
+            functions: HashMap::new(),
+            structs: HashMap::new(),
+            scopes: vec![Scope::new(None)],
+            scope_cnt: 0,
+            last_label: None,
+            labels: Vec::new(),
+            cur_func: None,
+            user_labels: HashSet::new(),
+            gotos: Vec::new(),
+            cur_line: None,
+            op_info: Vec::new(),
         }
     }
 
+    // enter a block scope
+    fn push_scope(&mut self) {
+        self.scopes.push(Scope::new(Some(self.scope_cnt)));
+        self.scope_cnt += 1;
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // declare a variable in the innermost scope
+    fn declare_var(&mut self, ident: &Token) -> Result<Token, String> {
+        if is_reserved(ident) {
+            return Err(format!("Cannot declare {} as a variable", ident.string));
+        }
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.vars.contains_key(&ident.string)
+            || scope.consts.contains_key(&ident.string)
+            || scope.arrays.contains(&ident.string)
+            || scope.struct_vars.contains_key(&ident.string)
+            || scope.struct_arrays.contains_key(&ident.string)
+        {
+            return Err(format!(
+                "Redeclaration of variable {} in the same block",
+                ident.string
+            ));
+        }
+        let name = match scope.id {
+            Some(id) => format!("{}@{}", ident.string, id),
+            None => ident.string.clone(),
+        };
+        scope.vars.insert(ident.string.clone(), name.clone());
+        let mut ret = ident.clone();
+        ret.string = name;
+        Ok(ret)
+    }
+
+    // "let" can declare a name again in the same block only as the same kind
+    fn check_redeclaration(&mut self, ident: &Token, kind: &'static str) -> Result<(), String> {
+        let scope = self.scopes.last_mut().unwrap();
+        let name = &ident.string;
+        let declared = if scope.vars.contains_key(name) {
+            "variable"
+        } else if scope.consts.contains_key(name) {
+            "constant"
+        } else {
+            match scope.lets.get(name) {
+                Some(declared) => declared,
+                None => {
+                    scope.lets.insert(name.clone(), kind);
+                    return Ok(());
+                }
+            }
+        };
+        if declared == kind {
+            return Ok(());
+        }
+        Err(format!(
+            "Redeclaration of {} in the same block: it is already declared as {}",
+            name, declared
+        ))
+    }
+
+    // "let a[...]" makes a refer to the array in the innermost scope
+    fn declare_array(&mut self, ident: &Token) -> Result<(), String> {
+        self.check_redeclaration(ident, "array")?;
+        let scope = self.scopes.last_mut().unwrap();
+        scope.arrays.insert(ident.string.clone());
+        Ok(())
+    }
+
+    // "let a: T" and "let a[...]: T" make a refer to the struct in the innermost scope
+    // returns the name in the internal code
+    // (variables of structs in a block scope are renamed like variables declared by "var")
+    fn declare_struct(
+        &mut self,
+        ident: &Token,
+        ty: &Token,
+        is_array: bool,
+    ) -> Result<Token, String> {
+        let kind = if is_array {
+            "array of structs"
+        } else {
+            "struct"
+        };
+        self.check_redeclaration(ident, kind)?;
+        let scope = self.scopes.last_mut().unwrap();
+        let map = if is_array {
+            &mut scope.struct_arrays
+        } else {
+            &mut scope.struct_vars
+        };
+        map.insert(ident.string.clone(), ty.string.clone());
+        Ok(if is_array {
+            ident.clone()
+        } else {
+            rename_in(scope, ident)
+        })
+    }
+
+    // the variable of a struct which tok refers to (renamed) and the name of the struct
+    fn struct_var(&self, tok: &Token) -> Option<(Token, String)> {
+        for scope in self.scopes.iter().rev() {
+            if scope.vars.contains_key(&tok.string) {
+                return None;
+            }
+            if let Some(ty) = scope.struct_vars.get(&tok.string) {
+                return Some((rename_in(scope, tok), ty.clone()));
+            }
+        }
+        None
+    }
+
+    // the name of the struct if name refers to a variable (or an array) of a struct
+    // variables declared in inner scopes (including parameters) hide them
+    fn struct_type(&self, name: &str, is_array: bool) -> Option<String> {
+        for scope in self.scopes.iter().rev() {
+            if scope.vars.contains_key(name) {
+                return None;
+            }
+            let map = if is_array {
+                &scope.struct_arrays
+            } else {
+                &scope.struct_vars
+            };
+            if let Some(ty) = map.get(name) {
+                return Some(ty.clone());
+            }
+        }
+        None
+    }
+
+    // whether name refers to an array
+    // variables declared in inner scopes (including parameters) hide arrays
+    fn is_array_name(&self, name: &str) -> bool {
+        for scope in self.scopes.iter().rev() {
+            if scope.vars.contains_key(name) {
+                return false;
+            }
+            if scope.arrays.contains(name) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // whether name is declared by "var" (or is a parameter) in the current scope
+    fn is_declared_var(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.vars.contains_key(name))
+    }
+
+    // rename a variable to the one declared in the innermost scope
+    // variables which are not declared are global (or local to a function call)
+    // constants are replaced with their values
+    fn resolve_var(&self, mut tok: Token) -> Token {
+        if let Some(mut val) = self.resolve_const(&tok) {
+            val.line = tok.line;
+            return val;
+        }
+        if is_bool_literal(&tok) {
+            return Token::new_bool(tok.matches("true"), tok.line);
+        }
+        if tok.ty == TokenType::Ident {
+            for scope in self.scopes.iter().rev() {
+                if let Some(name) = scope.vars.get(&tok.string) {
+                    tok.string = name.clone();
+                    break;
+                }
+            }
+        }
+        tok
+    }
+
+    // the value of the constant if tok refers to a constant
+    fn resolve_const(&self, tok: &Token) -> Option<Token> {
+        if tok.ty != TokenType::Ident {
+            return None;
+        }
+        for scope in self.scopes.iter().rev() {
+            // variables declared in inner scopes hide constants
+            if scope.vars.contains_key(&tok.string) {
+                return None;
+            }
+            if let Some(val) = scope.consts.get(&tok.string) {
+                return Some(val.clone());
+            }
+        }
+        None
+    }
+
+    fn check_assignable(&self, tok: &Token) -> Result<(), String> {
+        if self.resolve_const(tok).is_some() || is_bool_literal(tok) {
+            return Err(format!("Cannot assign a value to constant {}", tok.string));
+        }
+        if self.struct_type(&tok.string, false).is_some() {
+            return Err(format!("Cannot assign a value to struct {}", tok.string));
+        }
+        if self.struct_type(&tok.string, true).is_some() {
+            return Err(format!("Cannot assign a value to array {}", tok.string));
+        }
+        Ok(())
+    }
+
+    // evaluate the initializer of a constant at self.expr_pos
+    // its operations are folded by constant propagation and then removed
+    // comparisons and logical operations make constants of bool
+    fn const_expr(&mut self, ident: &Token) -> Result<Token, String> {
+        let code_start = self.internal_code.len();
+        let labels_start = self.labels.len();
+        self.reset_temp_vars();
+        let val = self.expr()?;
+        let mut ops = self.internal_code.split_off(code_start);
+        self.op_info.truncate(code_start);
+        // the last operation which writes the value
+        let is_bool = ops.iter().rev().find_map(|op| match op {
+            Operation::Copy(ref dist, ref src) if *dist == val => {
+                Some(src.matches("true") || src.matches("false"))
+            }
+            Operation::Eq(ref dist, ..)
+            | Operation::Ne(ref dist, ..)
+            | Operation::Lt(ref dist, ..)
+            | Operation::Le(ref dist, ..)
+            | Operation::Gt(ref dist, ..)
+            | Operation::Ge(ref dist, ..)
+                if *dist == val =>
+            {
+                Some(true)
+            }
+            _ => None,
+        });
+        let not_constant = format!("Initializer of constant {} is not constant", ident.string);
+        // initializers cannot change variables or arrays
+        let has_side_effects = ops.iter().any(|op| match op {
+            Operation::Copy(ref dist, _) => !dist.string.starts_with("_tmp"),
+            Operation::ArraySet(..) => true,
+            _ => false,
+        });
+        if has_side_effects {
+            return Err(not_constant);
+        }
+        // the value is read at the end
+        ops.push(Operation::Nop);
+        let mut label_map = VariableMap::new();
+        label_map.int_width = self.lexer.int_width;
+        for (label, line) in self.labels.drain(labels_start..) {
+            label_map.label_set(&label, (line - code_start) as i32);
+        }
+        let const_maps = cfg::ic_to_cfg(&ops, &mut label_map).constant_propagation();
+        let outs = &const_maps.last().unwrap().outs;
+        match val.ty {
+            TokenType::NumLiteral(_) | TokenType::BigLiteral(_) | TokenType::FloatLiteral(_) => {
+                Ok(Token { line: None, ..val })
+            }
+            TokenType::Ident => match outs.get(&val.string).cloned().flatten() {
+                Some(Value::Int(n)) if is_bool == Some(true) => Ok(Token::new_bool(n != 0, None)),
+                Some(Value::Str(_)) | None => Err(not_constant),
+                Some(val) => Ok(Token::from_value(&val, None)),
+            },
+            _ => Err(not_constant),
+        }
+    }
+
+    // labels in a function "f" are renamed to "_func_f.label"
+    // so that each function has its own labels
+    fn user_label(&self, label: &Token) -> Token {
+        let mut ret = label.clone();
+        if let Some(ref func) = self.cur_func {
+            ret.string = format!("{}.{}", func.string, label.string);
+        }
+        ret
+    }
+
+    // gotos can jump only to the labels in the same function (or at the top level)
+    fn check_gotos(&self, var: &VariableMap) -> Result<(), String> {
+        for (label, target) in &self.gotos {
+            if self.user_labels.contains(&target.string)
+                || var.label_map.contains_key(&target.string)
+            {
+                continue;
+            }
+            let suffix = format!(".{}", label.string);
+            let in_other_func = self
+                .user_labels
+                .iter()
+                .chain(var.label_map.keys())
+                .any(|name| *name == label.string || name.ends_with(&suffix));
+            return Err(if in_other_func {
+                format!("Cannot jump to label {} in another function", label.string)
+            } else {
+                format!("Undefined label: {}", label.string)
+            });
+        }
+        Ok(())
+    }
+
+    // put a label on the next operation
+    fn set_label(&mut self, label: &Token) {
+        self.labels.push((label.clone(), self.internal_code.len()));
+    }
+
+    // temporary variables are reused in each statement
+    fn reset_temp_vars(&mut self) {
+        self.temp_var_cnt = 0;
+        self.temp_var_gen += 1;
+    }
+
     fn make_temp_var(&mut self) -> Token {
         let ret = Token::new(
             format!("_tmp{}", self.temp_var_cnt),
@@ -248,119 +943,401 @@ impl Parser {
         let ident = self.lexer.tokens[self.expr_pos].clone();
         self.expr_pos += 1;
 
-        // ident[ expr ]
+        // ident( expr, ... )
+        if ident.ty == TokenType::Ident && self.lexer.tokens[self.expr_pos].matches("(") {
+            self.expr_pos += 1; // "("
+                                // len(a) for arrays
+            if ident.matches("len")
+                && self.is_array(self.expr_pos)
+                && self.lexer.tokens[self.expr_pos + 1].matches(")")
+            {
+                let array = self.lexer.tokens[self.expr_pos].clone();
+                self.expr_pos += 2; // a )
+                let ret = self.make_temp_var();
+                self.push_internal_code(Operation::ArrayLen(ret.clone(), array));
+                return Ok(ret);
+            }
+            // len(a) for arrays of structs (the length of the array of the first field)
+            if ident.matches("len") && self.lexer.tokens[self.expr_pos + 1].matches(")") {
+                let mut array = self.lexer.tokens[self.expr_pos].clone();
+                if let Some(fields) = self
+                    .struct_type(&array.string, true)
+                    .map(|ty| &self.structs[&ty])
+                {
+                    array = field_of(&array, &fields[0]);
+                    self.expr_pos += 2; // a )
+                    let ret = self.make_temp_var();
+                    self.push_internal_code(Operation::ArrayLen(ret.clone(), array));
+                    return Ok(ret);
+                }
+            }
+            let mut args = Vec::new();
+            if !self.lexer.tokens[self.expr_pos].matches(")") {
+                loop {
+                    args.push(self.expr()?);
+                    if !self.lexer.tokens[self.expr_pos].matches(",") {
+                        break;
+                    }
+                    self.expr_pos += 1; // ","
+                }
+            }
+            if !self.lexer.tokens[self.expr_pos].matches(")") {
+                return Err(String::from("Missing parentheses"));
+            }
+            self.expr_pos += 1; // ")"
+            let builtin = BUILTIN_FUNCTIONS
+                .iter()
+                .find(|(name, _)| ident.matches(name));
+            let arity = match (builtin, self.functions.get(&ident.string)) {
+                (Some((_, arity)), _) => *arity,
+                (None, Some(func)) => func.params.len(),
+                (None, None) => return Err(format!("Undefined function: {}", ident.string)),
+            };
+            if arity != args.len() {
+                return Err(format!(
+                    "Function {} takes {} argument(s) but {} were given",
+                    ident.string,
+                    arity,
+                    args.len()
+                ));
+            }
+            let ret = self.make_temp_var();
+            // user-defined functions cannot have the names of builtin functions
+            let op = match ident.string.as_str() {
+                "len" => Operation::Len(ret.clone(), args.pop().unwrap()),
+                "int" => Operation::ToInt(ret.clone(), args.pop().unwrap()),
+                "float" => Operation::ToFloat(ret.clone(), args.pop().unwrap()),
+                "input" => Operation::Input(ret.clone()),
+                "inputLine" => Operation::InputLine(ret.clone()),
+                "eof" => Operation::Eof(ret.clone()),
+                _ => Operation::Call(ret.clone(), ident, args),
+            };
+            self.push_internal_code(op);
+            return Ok(ret);
+        }
+
+        if self.is_array(self.expr_pos - 1) {
+            return Err(format!("Array {} cannot be used as a value", ident.string));
+        }
+        // ident.field
+        if let Some((ident, ty)) = self.struct_var(&ident) {
+            return self.struct_field(&ident, &ty);
+        }
+        // ident[ expr ][ expr ]....field
+        if let Some(ty) = self.struct_type(&ident.string, true) {
+            if !self.lexer.tokens[self.expr_pos].matches("[") {
+                return Err(format!("Array {} cannot be used as a value", ident.string));
+            }
+            let indices = self.indices()?;
+            let array = self.struct_field(&ident, &ty)?;
+            let ret = self.make_temp_var();
+            self.push_internal_code(Operation::ArrayGet(ret.clone(), array, indices));
+            return Ok(ret);
+        }
+        if self.lexer.tokens[self.expr_pos].matches(".") {
+            return Err(format!("{} is not a struct", ident.string));
+        }
+        // ident[ expr ][ expr ]...
         if self.lexer.tokens[self.expr_pos].matches("[") {
+            let indices = self.indices()?;
+            let ret = self.make_temp_var();
+            self.push_internal_code(Operation::ArrayGet(ret.clone(), ident, indices));
+            return Ok(ret);
+        }
+        Ok(self.resolve_var(ident))
+    }
+
+    // [ expr ][ expr ]... (sizes of arrays or indices of elements)
+    fn indices(&mut self) -> Result<Vec<Token>, String> {
+        let mut indices = Vec::new();
+        while self.lexer.tokens[self.expr_pos].matches("[") {
             self.expr_pos += 1; // "["
-            let index = self.expr()?;
+            indices.push(self.expr()?);
             if !self.lexer.tokens[self.expr_pos].matches("]") {
                 return Err(String::from("Unmatched parentheses"));
             }
             self.expr_pos += 1; // "]"
-            let ret = self.make_temp_var();
-            self.push_internal_code(Operation::ArrayGet(ret.clone(), ident, index));
-            return Ok(ret);
         }
-        Ok(ident)
+        Ok(indices)
     }
 
     fn unary(&mut self) -> Result<Token, String> {
         if self.lexer.tokens[self.expr_pos].matches("-") {
             self.expr_pos += 1;
             let tmp = self.make_temp_var();
-            let op = Operation::Sub(tmp.clone(), Token::new_num(0, None), self.primary()?);
+            let op = Operation::Sub(tmp.clone(), Token::new_num(0, None), self.unary()?);
             self.push_internal_code(op);
             return Ok(tmp);
         } else if self.lexer.tokens[self.expr_pos].matches("+") {
             self.expr_pos += 1;
-            return Ok(self.primary()?);
+            return self.unary();
+        } else if self.lexer.tokens[self.expr_pos].matches("!") {
+            self.expr_pos += 1;
+            let tmp = self.make_temp_var();
+            let val = self.unary()?;
+            self.push_truth_test(true, tmp.clone(), val);
+            return Ok(tmp);
+        } else if self.lexer.tokens[self.expr_pos].matches("~") {
+            // ~a = a ^ -1
+            self.expr_pos += 1;
+            let tmp = self.make_temp_var();
+            let op = Operation::BitXor(tmp.clone(), self.unary()?, Token::new_num(-1, None));
+            self.push_internal_code(op);
+            return Ok(tmp);
         }
         self.primary()
     }
 
-    parse_binary_op!(mul, unary, "*", Operation::Mul, "/", Operation::Div);
-    parse_binary_op!(add, mul, "+", Operation::Add, "-", Operation::Sub);
-    parse_binary_op!(relational, add, "<", Operation::Lt, "<=", Operation::Le);
     parse_binary_op!(
-        equality,
+        mul,
+        unary,
+        "*" => Operation::Mul,
+        "/" => Operation::Div,
+        "%" => Operation::Mod
+    );
+    parse_binary_op!(add, mul, "+" => Operation::Add, "-" => Operation::Sub);
+    parse_binary_op!(shift, add, "<<" => Operation::Shl, ">>" => Operation::Shr);
+    parse_binary_op!(
         relational,
-        "==",
-        Operation::Eq,
-        "!=",
-        Operation::Ne
+        shift,
+        "<" => Operation::Lt,
+        "<=" => Operation::Le,
+        ">" => Operation::Gt,
+        ">=" => Operation::Ge
     );
 
-    // TODO: assign to array
-    fn assign(&mut self) -> Result<Token, String> {
-        let equality = self.equality()?;
-        if self.lexer.tokens[self.expr_pos].matches("=") {
-            if equality.ty != TokenType::Ident {
-                return Err(format!(
-                    "rhs of '=' must be Ident, but found {}",
-                    equality.string
-                ));
-            }
+    // operands of equality operators can be arrays
+    fn equality_operand(&mut self) -> Result<Token, String> {
+        if self.is_array(self.expr_pos) {
             self.expr_pos += 1;
-            let assign = self.assign()?;
-            self.push_internal_code(Operation::Copy(equality, assign.clone()));
-            return Ok(assign);
-        } else if self.lexer.tokens[self.expr_pos].matches("+=") {
-            if equality.ty != TokenType::Ident {
-                return Err(format!(
-                    "rhs of '+=' must be Ident, but found {}",
-                    equality.string
-                ));
+            return Ok(self.lexer.tokens[self.expr_pos - 1].clone());
+        }
+        self.relational()
+    }
+
+    // a != b for arrays
+    // ↓
+    // ArrayEq(t, a, b)
+    // Eq(n, t, 0)
+    fn equality(&mut self) -> Result<Token, String> {
+        let start_pos = self.expr_pos;
+        let mut ret = self.equality_operand()?;
+        let mut is_array = self.is_array(start_pos);
+        loop {
+            let op = self.lexer.tokens[self.expr_pos].clone();
+            if !op.matches("==") && !op.matches("!=") {
+                break;
             }
             self.expr_pos += 1;
-            let assign = self.assign()?;
-            self.push_internal_code(Operation::Add(
-                equality.clone(),
-                equality.clone(),
-                assign.clone(),
-            ))
-        } else if self.lexer.tokens[self.expr_pos].matches("-=") {
-            if equality.ty != TokenType::Ident {
-                return Err(format!(
-                    "rhs of '-=' must be Ident, but found {}",
-                    equality.string
-                ));
+            let rhs_pos = self.expr_pos;
+            let rhs = self.equality_operand()?;
+            let tmp = self.make_temp_var();
+            match (is_array, self.is_array(rhs_pos)) {
+                (false, false) if op.matches("==") => {
+                    self.push_internal_code(Operation::Eq(tmp.clone(), ret, rhs))
+                }
+                (false, false) => self.push_internal_code(Operation::Ne(tmp.clone(), ret, rhs)),
+                (true, true) => {
+                    self.push_internal_code(Operation::ArrayEq(tmp.clone(), ret, rhs));
+                    if op.matches("!=") {
+                        self.push_truth_test(true, tmp.clone(), tmp.clone());
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "Cannot compare an array with a value: {} {}",
+                        ret.string, rhs.string
+                    ))
+                }
             }
+            ret = tmp;
+            is_array = false;
+        }
+        if is_array {
+            return Err(format!("Array {} cannot be used as a value", ret.string));
+        }
+        Ok(ret)
+    }
+    parse_binary_op!(bit_and, equality, "&" => Operation::BitAnd);
+    parse_binary_op!(bit_xor, bit_and, "^" => Operation::BitXor);
+    parse_binary_op!(bit_or, bit_xor, "|" => Operation::BitOr);
+
+    // Parsing logical and (the rhs is evaluated only if the lhs is true)
+    // a && b
+    // ↓
+    // Ne(t, a, 0)
+    // Eq(n, t, 0)
+    // IfGoto(n, L0)
+    // Ne(t, b, 0)
+    // L0:
+    fn logical_and(&mut self) -> Result<Token, String> {
+        let lhs = self.bit_or()?;
+        if !self.lexer.tokens[self.expr_pos].matches("&&") {
+            return Ok(lhs);
+        }
+        let ret = self.make_temp_var();
+        self.push_truth_test(false, ret.clone(), lhs);
+        while self.lexer.tokens[self.expr_pos].matches("&&") {
             self.expr_pos += 1;
-            let assign = self.assign()?;
-            self.push_internal_code(Operation::Sub(
-                equality.clone(),
-                equality.clone(),
-                assign.clone(),
-            ))
-        } else if self.lexer.tokens[self.expr_pos].matches("*=") {
-            if equality.ty != TokenType::Ident {
+            let label0 = self.make_temp_label();
+            let not_ret = self.make_temp_var();
+            self.push_truth_test(true, not_ret.clone(), ret.clone());
+            self.push_internal_code(Operation::IfGoto(not_ret, label0.clone()));
+            let rhs = self.bit_or()?;
+            self.push_truth_test(false, ret.clone(), rhs);
+            self.set_label(&label0);
+        }
+        Ok(ret)
+    }
+
+    // Parsing logical or (the rhs is evaluated only if the lhs is false)
+    // a || b
+    // ↓
+    // Ne(t, a, 0)
+    // IfGoto(t, L0)
+    // Ne(t, b, 0)
+    // L0:
+    fn logical_or(&mut self) -> Result<Token, String> {
+        let lhs = self.logical_and()?;
+        if !self.lexer.tokens[self.expr_pos].matches("||") {
+            return Ok(lhs);
+        }
+        let ret = self.make_temp_var();
+        self.push_truth_test(false, ret.clone(), lhs);
+        while self.lexer.tokens[self.expr_pos].matches("||") {
+            self.expr_pos += 1;
+            let label0 = self.make_temp_label();
+            self.push_internal_code(Operation::IfGoto(ret.clone(), label0.clone()));
+            let rhs = self.logical_and()?;
+            self.push_truth_test(false, ret.clone(), rhs);
+            self.set_label(&label0);
+        }
+        Ok(ret)
+    }
+
+    // Parsing conditional operator (right-associative)
+    // c ? a : b
+    // ↓
+    // Eq(n, c, 0)
+    // IfGoto(n, L0)
+    // Copy(t, a)
+    // Goto(L1)
+    // L0:
+    // Copy(t, b)
+    // L1:
+    fn conditional(&mut self) -> Result<Token, String> {
+        let cond = self.logical_or()?;
+        if !self.lexer.tokens[self.expr_pos].matches("?") {
+            return Ok(cond);
+        }
+        self.expr_pos += 1; // "?"
+        let label0 = self.make_temp_label();
+        let label1 = self.make_temp_label();
+        let ret = self.make_temp_var();
+        let not_cond = self.make_temp_var();
+        self.push_truth_test(true, not_cond.clone(), cond);
+        self.push_internal_code(Operation::IfGoto(not_cond, label0.clone()));
+        let then_val = self.expr()?;
+        self.push_internal_code(Operation::Copy(ret.clone(), then_val));
+        self.push_internal_code(Operation::Goto(label1.clone()));
+        if !self.lexer.tokens[self.expr_pos].matches(":") {
+            return Err(String::from("Missing : of conditional operator"));
+        }
+        self.expr_pos += 1; // ":"
+        self.set_label(&label0);
+        let else_val = self.conditional()?;
+        self.push_internal_code(Operation::Copy(ret.clone(), else_val));
+        self.set_label(&label1);
+        Ok(ret)
+    }
+
+    fn assign(&mut self) -> Result<Token, String> {
+        let lhs_pos = self.expr_pos;
+        let lhs = self.conditional()?;
+        let op_tok = self.lexer.tokens[self.expr_pos].clone();
+        let is_assignment = op_tok.matches("=")
+            || COMPOUND_ASSIGNMENTS
+                .iter()
+                .any(|(op, _)| op_tok.matches(op));
+        // constants have already been replaced with their values
+        if is_assignment && lhs_pos + 1 == self.expr_pos {
+            self.check_assignable(&self.lexer.tokens[lhs_pos])?;
+        }
+        // lhs is an array element if the last operation reads it
+        let elem = match self.internal_code.last() {
+            Some(Operation::ArrayGet(ref dist, ref name, ref indices)) if *dist == lhs => {
+                Some((name.clone(), indices.clone()))
+            }
+            _ => None,
+        };
+        if let Some((name, indices)) = elem {
+            return self.assign_elem(lhs, name, indices);
+        }
+        if op_tok.matches("=") {
+            if lhs.ty != TokenType::Ident {
                 return Err(format!(
-                    "rhs of '*=' must be Ident, but found {}",
-                    equality.string
+                    "lhs of '=' must be Ident, but found {}",
+                    lhs.string
                 ));
             }
             self.expr_pos += 1;
             let assign = self.assign()?;
-            self.push_internal_code(Operation::Mul(
-                equality.clone(),
-                equality.clone(),
-                assign.clone(),
-            ))
-        } else if self.lexer.tokens[self.expr_pos].matches("/=") {
-            if equality.ty != TokenType::Ident {
-                return Err(format!(
-                    "rhs of '/=' must be Ident, but found {}",
-                    equality.string
-                ));
+            self.push_internal_code(Operation::Copy(lhs, assign.clone()));
+            return Ok(assign);
+        }
+        for (op, path) in COMPOUND_ASSIGNMENTS.iter() {
+            if op_tok.matches(op) {
+                if lhs.ty != TokenType::Ident {
+                    return Err(format!(
+                        "lhs of '{}' must be Ident, but found {}",
+                        op, lhs.string
+                    ));
+                }
+                self.expr_pos += 1;
+                let assign = self.assign()?;
+                self.push_internal_code(path(lhs.clone(), lhs.clone(), assign));
+                break;
             }
+        }
+        Ok(lhs)
+    }
+
+    // Parsing assignment to array elements
+    // a[i] = e
+    // ↓
+    // ArraySet(a, i, e)
+    //
+    // a[i] += e
+    // ↓
+    // ArrayGet(t, a, i)
+    // Add(t, t, e)
+    // ArraySet(a, i, t)
+    fn assign_elem(
+        &mut self,
+        lhs: Token,
+        name: Token,
+        indices: Vec<Token>,
+    ) -> Result<Token, String> {
+        let op_tok = self.lexer.tokens[self.expr_pos].clone();
+        if op_tok.matches("=") {
+            // the element is not read
+            self.internal_code.pop();
+            self.op_info.pop();
             self.expr_pos += 1;
             let assign = self.assign()?;
-            self.push_internal_code(Operation::Div(
-                equality.clone(),
-                equality.clone(),
-                assign.clone(),
-            ))
+            self.push_internal_code(Operation::ArraySet(name, indices, assign.clone()));
+            return Ok(assign);
         }
-        Ok(equality)
+        for (op, path) in COMPOUND_ASSIGNMENTS.iter() {
+            if op_tok.matches(op) {
+                self.expr_pos += 1;
+                let assign = self.assign()?;
+                self.push_internal_code(path(lhs.clone(), lhs.clone(), assign));
+                self.push_internal_code(Operation::ArraySet(name, indices, lhs.clone()));
+                break;
+            }
+        }
+        Ok(lhs)
     }
 
     // parse an expression whose starts from self.expr_pos
@@ -382,73 +1359,199 @@ impl Parser {
         if self.lexer.tokens[start_pos].matches(";") {
             return None;
         }
+        self.cur_line = self.lexer.tokens[start_pos].line;
         // Easily analyze programs by using each temporary variable once
         // Do not modify below:
-        // self.temp_var_cnt = 0;
+        // self.reset_temp_vars();
         self.expr_pos = start_pos;
         Some(self.expr())
     }
 
     fn evaluate_expr(&mut self, start_pos: usize) -> Result<Token, String> {
-        self.temp_var_cnt = 0;
+        self.reset_temp_vars();
         self.expr_pos = start_pos;
         self.expr()
     }
 
+    // evaluate "e0, e1, ... ;" starting at self.pos
+    // Temporary variables are not reused, so all the values are available at the end.
+    fn expr_list(&mut self) -> Result<Vec<Token>, String> {
+        self.reset_temp_vars();
+        let mut vals = Vec::new();
+        loop {
+            let tok = &self.lexer.tokens[self.pos];
+            if tok.matches(";") || tok.matches(",") {
+                return Err(format!("Missing expression before {}", tok.string));
+            }
+            self.expr_len(self.pos)?;
+            self.expr_pos = self.pos;
+            vals.push(self.expr()?);
+            self.pos = self.expr_pos;
+            if self.lexer.tokens[self.pos].matches(";") {
+                self.pos += 1;
+                return Ok(vals);
+            }
+            if !self.lexer.tokens[self.pos].matches(",") {
+                return Err(format!(
+                    "Expected , or ; but found {}",
+                    self.lexer.tokens[self.pos].string
+                ));
+            }
+            self.pos += 1;
+        }
+    }
+
+    // e0, e1, ... } (self.expr_pos points to e0)
+    fn initializer_list(&mut self) -> Result<Vec<Token>, String> {
+        let mut vals = Vec::new();
+        if self.lexer.tokens[self.expr_pos].matches("}") {
+            self.expr_pos += 1;
+            return Ok(vals);
+        }
+        loop {
+            vals.push(self.expr()?);
+            if self.lexer.tokens[self.expr_pos].matches("}") {
+                self.expr_pos += 1;
+                return Ok(vals);
+            }
+            if !self.lexer.tokens[self.expr_pos].matches(",") {
+                return Err(format!(
+                    "Expected , or }} but found {}",
+                    self.lexer.tokens[self.expr_pos].string
+                ));
+            }
+            self.expr_pos += 1;
+        }
+    }
+
+    // elements are set one by one so that the optimizer can see the values
+    fn array_new_with_list(&mut self, name: Token, sizes: Vec<Token>, vals: Vec<Token>) {
+        let zero = Token::new_num(0, None);
+        self.push_internal_code(Operation::ArrayNew(name.clone(), sizes, zero));
+        for (i, val) in vals.into_iter().enumerate() {
+            let index = Token::new_num(i as i64, None);
+            self.push_internal_code(Operation::ArraySet(name.clone(), vec![index], val));
+        }
+    }
+
+    // check ";" at self.expr_pos and move self.pos next to it
+    fn expect_semicolon(&mut self) -> Result<(), String> {
+        if !self.lexer.tokens[self.expr_pos].matches(";") {
+            return Err(format!(
+                "Expected ; but found {}",
+                self.lexer.tokens[self.expr_pos].string
+            ));
+        }
+        self.pos = self.expr_pos + 1;
+        Ok(())
+    }
+
     fn push_internal_code(&mut self, op: Operation) {
         self.internal_code.push(op);
+        self.op_info.push(OpInfo {
+            line: self.cur_line,
+            temp_var_gen: self.temp_var_gen,
+            truth_test: false,
+        });
+    }
+
+    // Eq(dist, val, 0) if negate otherwise Ne(dist, val, 0)
+    fn push_truth_test(&mut self, negate: bool, dist: Token, val: Token) {
+        let zero = Token::new_num(0, None);
+        self.push_internal_code(if negate {
+            Operation::Eq(dist, val, zero)
+        } else {
+            Operation::Ne(dist, val, zero)
+        });
+        self.op_info.last_mut().unwrap().truth_test = true;
     }
 
     fn expr_len(&self, mut start_pos: usize) -> Result<usize, String> {
         let mut len = 0;
-        match self.lexer.tokens[start_pos].string.as_str() {
-            "(" => {
+        // unary operators
+        while is_unary_operator(&self.lexer.tokens[start_pos]) {
+            start_pos += 1;
+            len += 1;
+        }
+        if self.lexer.tokens[start_pos].matches("(") {
+            start_pos += 1;
+            len += 1;
+            let inside_len = self.expr_len(start_pos)?;
+            start_pos += inside_len;
+            len += inside_len;
+            if !self.lexer.tokens[start_pos].matches(")") {
+                return Err(String::from("Missing closing parentheses \")\""));
+            }
+            start_pos += 1;
+            len += 1;
+        } else {
+            //numerical literals or variables
+            start_pos += 1;
+            len += 1;
+
+            if self.lexer.tokens[start_pos].matches("[") {
+                let indices_len = self.indices_len(start_pos)?;
+                start_pos += indices_len;
+                len += indices_len;
+            } else if self.lexer.tokens[start_pos].matches("(") {
+                // function call
                 start_pos += 1;
                 len += 1;
-                let inside_len = self.expr_len(start_pos)?;
-                start_pos += inside_len;
-                len += inside_len;
+                if !self.lexer.tokens[start_pos].matches(")") {
+                    loop {
+                        let arg_len = self.expr_len(start_pos)?;
+                        start_pos += arg_len;
+                        len += arg_len;
+                        if !self.lexer.tokens[start_pos].matches(",") {
+                            break;
+                        }
+                        start_pos += 1;
+                        len += 1;
+                    }
+                }
                 if !self.lexer.tokens[start_pos].matches(")") {
                     return Err(String::from("Missing closing parentheses \")\""));
                 }
-                //start_pos += 1;
-                len += 1;
-                Ok(len)
-            }
-            _ => {
-                //numerical literals or variables
                 start_pos += 1;
                 len += 1;
+            }
+            // fields of structs
+            if self.lexer.tokens[start_pos].matches(".") {
+                start_pos += 2;
+                len += 2;
+            }
+        }
 
-                if self.lexer.tokens[start_pos].matches("[") {
-                    start_pos += 1;
-                    len += 1;
-                    let inside_len = self.expr_len(start_pos)?;
-                    start_pos += inside_len;
-                    len += inside_len;
-                    if !self.lexer.tokens[start_pos].matches("]") {
-                        return Err(String::from("Missing closing parentheses \"]\""));
-                    }
-                    start_pos += 1;
-                    len += 1;
-                }
+        while start_pos < self.lexer.tokens.len() {
+            if is_binary_operator(&self.lexer.tokens[start_pos]) {
+                start_pos += 1; // eat 1 token
+                len += 1;
+                let rhs_len = self.expr_len(start_pos)?;
+                start_pos += rhs_len;
+                len += rhs_len;
+            } else {
+                break;
+            }
+        }
+        Ok(len)
+    }
 
-                while start_pos < self.lexer.tokens.len() {
-                    if let "+" | "-" | "*" | "/" | "==" | "!=" | "<" | "<=" | "=" | "+=" | "-="
-                    | "*=" | "/=" = self.lexer.tokens[start_pos].string.as_str()
-                    {
-                        start_pos += 1; // eat 1 token
-                        len += 1;
-                        let rhs_len = self.expr_len(start_pos)?;
-                        start_pos += rhs_len;
-                        len += rhs_len;
-                    } else {
-                        break;
-                    }
-                }
-                Ok(len)
+    // the length of "[ expr ][ expr ]..." starting at start_pos
+    fn indices_len(&self, mut start_pos: usize) -> Result<usize, String> {
+        let mut len = 0;
+        while self.lexer.tokens[start_pos].matches("[") {
+            start_pos += 1;
+            len += 1;
+            let inside_len = self.expr_len(start_pos)?;
+            start_pos += inside_len;
+            len += inside_len;
+            if !self.lexer.tokens[start_pos].matches("]") {
+                return Err(String::from("Missing closing parentheses \"]\""));
             }
+            start_pos += 1;
+            len += 1;
         }
+        Ok(len)
     }
 
     // This function set self.cur_token_param_start_pos, and add up self.pos
@@ -458,20 +1561,20 @@ impl Parser {
     // *tXX: any token, *eXX: any expression (length > 0), **eXX: any expression (length >= 0. If length = 0, the beginning must be ";" or ")")
     fn phrase_compare<const N: usize>(&mut self, phr: [&'static str; N]) -> Result<bool, String> {
         let inst_start_pos = self.pos;
-        for i in 0..N {
-            if phr[i].starts_with("*t") {
-                let n = phr[i][2..].parse::<usize>().unwrap();
+        for word in phr.iter() {
+            if let Some(n) = word.strip_prefix("*t") {
+                let n = n.parse::<usize>().unwrap();
                 self.cur_token_param[n] = Some(self.lexer.tokens[self.pos].clone());
                 self.pos += 1;
                 continue;
-            } else if phr[i].starts_with("*e") {
-                let n = phr[i][2..].parse::<usize>().unwrap();
+            } else if let Some(n) = word.strip_prefix("*e") {
+                let n = n.parse::<usize>().unwrap();
                 self.cur_expr_param_start_pos[n] = self.pos;
                 let expr_len = self.expr_len(self.pos)?;
                 self.pos += expr_len;
                 continue;
-            } else if phr[i].starts_with("**e") {
-                let n = phr[i][3..].parse::<usize>().unwrap();
+            } else if let Some(n) = word.strip_prefix("**e") {
+                let n = n.parse::<usize>().unwrap();
                 self.cur_expr_param_start_pos[n] = self.pos;
                 let start_tok = &self.lexer.tokens[self.pos];
                 // FIXME: dirty
@@ -480,7 +1583,7 @@ impl Parser {
                     self.pos += expr_len;
                 }
                 continue;
-            } else if !self.lexer.tokens[self.pos].matches(phr[i]) {
+            } else if !self.lexer.tokens[self.pos].matches(word) {
                 // unwind
                 self.pos = inst_start_pos;
                 return Ok(false);
@@ -491,48 +1594,613 @@ impl Parser {
         Ok(true)
     }
 
+    // parse "( ident, ... )" starting at start_pos
+    // returns the parameters and the position next to ")"
+    fn parse_params(&self, mut start_pos: usize) -> Result<(Vec<Token>, usize), String> {
+        let mut params: Vec<Token> = Vec::new();
+        if !self.lexer.tokens[start_pos].matches("(") {
+            return Err(String::from("Missing parentheses"));
+        }
+        start_pos += 1;
+        if self.lexer.tokens[start_pos].matches(")") {
+            return Ok((params, start_pos + 1));
+        }
+        loop {
+            let param = self.lexer.tokens[start_pos].clone();
+            if param.ty != TokenType::Ident {
+                return Err(format!("Invalid parameter: {}", param.string));
+            }
+            if params.contains(&param) {
+                return Err(format!("Duplicate parameter: {}", param.string));
+            }
+            params.push(param);
+            start_pos += 1;
+            if self.lexer.tokens[start_pos].matches(")") {
+                return Ok((params, start_pos + 1));
+            }
+            if !self.lexer.tokens[start_pos].matches(",") {
+                return Err(String::from("Missing parentheses"));
+            }
+            start_pos += 1;
+        }
+    }
+
+    // collect the names of arrays declared by "let a[...]" or copied by "b = a;"
+    // into the top level scope so that arrays can be used before their declarations
+    // (parameters and variables declared by "var" hide them)
+    // Arrays which were declared in interactive mode are also included.
+    fn scan_arrays(&mut self, var: &VariableMap) {
+        let arrays = &mut self.scopes[0].arrays;
+        arrays.extend(var.array_names().cloned());
+        let tokens = &self.lexer.tokens;
+        for i in 0..tokens.len() - 3 {
+            if tokens[i].matches("let") && tokens[i + 2].matches("[") {
+                arrays.insert(tokens[i + 1].string.clone());
+            }
+        }
+        let copies = scan_copies(tokens);
+        loop {
+            let mut changed = false;
+            for (dist, src) in &copies {
+                if arrays.contains(src) {
+                    changed |= arrays.insert(dist.clone());
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    // whether tokens[pos] is an array name without indices
+    fn is_array(&self, pos: usize) -> bool {
+        let tok = &self.lexer.tokens[pos];
+        tok.ty == TokenType::Ident
+            && self.is_array_name(&tok.string)
+            && !self.lexer.tokens[pos + 1].matches("[")
+    }
+
+    // collect all function definitions before compiling
+    // so that functions can be called before they are defined
+    fn scan_functions(&mut self) -> Result<(), String> {
+        for i in 0..self.lexer.tokens.len() - 3 {
+            if !self.lexer.tokens[i].matches("func") {
+                continue;
+            }
+            if is_reserved(&self.lexer.tokens[i + 1]) {
+                return Err(format!(
+                    "Cannot declare {} as a function",
+                    self.lexer.tokens[i + 1].string
+                ));
+            }
+            let name = self.lexer.tokens[i + 1].string.clone();
+            if BUILTIN_FUNCTIONS
+                .iter()
+                .any(|(builtin, _)| *builtin == name)
+            {
+                return Err(format!("Cannot redefine builtin function: {}", name));
+            }
+            if self.functions.contains_key(&name) {
+                return Err(format!("Redefinition of function: {}", name));
+            }
+            let (params, _) = self.parse_params(i + 2)?;
+            let label = Token::new(format!("_func_{}", name), TokenType::Ident);
+            self.functions.insert(name, Function { label, params });
+        }
+        Ok(())
+    }
+
+    // collect all struct definitions "struct name { field; ... }" before compiling
+    // Structs which were defined in interactive mode are also included.
+    fn scan_structs(&mut self, var: &VariableMap) -> Result<(), String> {
+        self.structs = var.structs.clone();
+        let tokens = &self.lexer.tokens;
+        for i in 0..tokens.len() - 3 {
+            if !tokens[i].matches("struct") || tokens[i + 1].ty != TokenType::Ident {
+                continue;
+            }
+            let name = tokens[i + 1].string.clone();
+            if !tokens[i + 2].matches("{") {
+                return Err(format!("Missing fields of struct {}", name));
+            }
+            if self.structs.contains_key(&name) {
+                return Err(format!("Redefinition of struct: {}", name));
+            }
+            let mut fields: Vec<String> = Vec::new();
+            let mut pos = i + 3;
+            while !tokens[pos].matches("}") {
+                if pos >= tokens.len() - 3 {
+                    return Err(format!("Missing }} of struct {}", name));
+                }
+                let field = &tokens[pos];
+                if field.ty != TokenType::Ident || !tokens[pos + 1].matches(";") {
+                    return Err(format!(
+                        "Invalid field of struct {}: {}",
+                        name, field.string
+                    ));
+                }
+                if fields.contains(&field.string) {
+                    return Err(format!(
+                        "Duplicate field {} of struct {}",
+                        field.string, name
+                    ));
+                }
+                fields.push(field.string.clone());
+                pos += 2;
+            }
+            if fields.is_empty() {
+                return Err(format!("Struct {} has no fields", name));
+            }
+            self.structs.insert(name, fields);
+        }
+        Ok(())
+    }
+
+    // collect the variables declared by "let a: T;", the arrays declared by "let a[...]: T;"
+    // and the ones copied by "b = a;" like scan_arrays
+    fn scan_struct_vars(&mut self, var: &VariableMap) {
+        let scope = &mut self.scopes[0];
+        scope.struct_vars = var.struct_vars.clone();
+        scope.struct_arrays = var.struct_arrays.clone();
+        let tokens = &self.lexer.tokens;
+        for i in 0..tokens.len() - 3 {
+            if !tokens[i].matches("let") {
+                continue;
+            }
+            // skip the sizes
+            let mut pos = i + 2;
+            let mut depth = 0;
+            while pos < tokens.len() - 3 && (depth > 0 || tokens[pos].matches("[")) {
+                if tokens[pos].matches("[") {
+                    depth += 1;
+                } else if tokens[pos].matches("]") {
+                    depth -= 1;
+                }
+                pos += 1;
+            }
+            if !tokens[pos].matches(":") {
+                continue;
+            }
+            let (name, ty) = (tokens[i + 1].string.clone(), tokens[pos + 1].string.clone());
+            if pos == i + 2 {
+                scope.struct_vars.insert(name, ty);
+            } else {
+                scope.struct_arrays.insert(name, ty);
+            }
+        }
+        let copies = scan_copies(tokens);
+        loop {
+            let mut changed = false;
+            for (dist, src) in &copies {
+                for map in [&mut scope.struct_vars, &mut scope.struct_arrays].iter_mut() {
+                    if let Some(ty) = map.get(src).cloned() {
+                        if let Entry::Vacant(e) = map.entry(dist.clone()) {
+                            e.insert(ty);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        // arrays of structs are not arrays of integers
+        for name in scope.struct_arrays.keys() {
+            scope.arrays.remove(name);
+        }
+    }
+
+    // the fields of the struct named tok
+    fn struct_fields(&self, tok: &Token) -> Result<Vec<String>, String> {
+        match self.structs.get(&tok.string) {
+            Some(fields) => Ok(fields.clone()),
+            None => Err(format!("Undefined struct: {}", tok.string)),
+        }
+    }
+
+    // ".field" after a variable or an element of an array of structs (self.expr_pos points to ".")
+    // returns the name of the variable (or the array) which holds the field
+    fn struct_field(&mut self, ident: &Token, ty: &str) -> Result<Token, String> {
+        if !self.lexer.tokens[self.expr_pos].matches(".") {
+            return Err(format!("Struct {} cannot be used as a value", ident.string));
+        }
+        let field = &self.lexer.tokens[self.expr_pos + 1];
+        let has_field = match self.structs.get(ty) {
+            Some(fields) => fields.contains(&field.string),
+            None => return Err(format!("Undefined struct: {}", ty)),
+        };
+        if !has_field {
+            return Err(format!("Struct {} has no field {}", ty, field.string));
+        }
+        let ret = field_of(ident, &field.string);
+        self.expr_pos += 2;
+        Ok(ret)
+    }
+
+    // b = a; for structs (or arrays of structs) copies all the fields
+    // returns false if a is not a struct
+    fn struct_copy(&mut self, dist_tok: &Token, src_tok: &Token) -> Result<bool, String> {
+        // variables of structs may be renamed but arrays of structs are not
+        let (src, ty, is_array) = match self.struct_var(src_tok) {
+            Some((src, ty)) => (src, ty, false),
+            None => match self.struct_type(&src_tok.string, true) {
+                Some(ty) => (src_tok.clone(), ty, true),
+                None => return Ok(false),
+            },
+        };
+        let dist = if is_array {
+            self.struct_type(&dist_tok.string, true)
+                .map(|ty| (dist_tok.clone(), ty))
+        } else {
+            self.struct_var(dist_tok)
+        };
+        let dist = match dist {
+            Some((dist, dist_ty)) if dist_ty == ty => dist,
+            _ => {
+                return Err(format!(
+                    "Cannot assign {} of struct {} to {}",
+                    src_tok.string, ty, dist_tok.string
+                ))
+            }
+        };
+        let ty = Token::new(ty, TokenType::Ident);
+        for field in self.struct_fields(&ty)? {
+            let (dist, src) = (field_of(&dist, &field), field_of(&src, &field));
+            self.push_internal_code(if is_array {
+                Operation::ArrayCopy(dist, src)
+            } else {
+                Operation::Copy(dist, src)
+            });
+        }
+        Ok(true)
+    }
+
+    // Parsing for statement
+    // for (**e0; **e1; **e2) {
+    //     A
+    // }
+    // ↓
+    // evaluate e0 (output if e0 exists)
+    // IfGoto(!e1, L0) (output if e1 exists)
+    // L1:
+    // A
+    // L2: (this label is referred by "continue")
+    // evaluate e2 (output if e2 exists)
+    // IfGoto(e1, L1) (Goto(L1) is output if e1 dosen't exist)
+    // L0:
+    // This function emits the code after e0
+    fn compile_for(&mut self, loop_name: Option<Token>) -> Result<(), String> {
+        let label0 = self.make_temp_label();
+        let label1 = self.make_temp_label();
+        let label2 = self.make_temp_label();
+        self.blocks.push(Block::For(
+            label0.clone(),
+            label1.clone(),
+            label2,
+            self.cur_expr_param_start_pos[1],
+            self.cur_expr_param_start_pos[2],
+            loop_name,
+        ));
+
+        let opt_expr1 = self.get_expr_opt_param(1);
+        if let Some(expr1) = opt_expr1 {
+            let not_expr1 = self.make_temp_var();
+            self.push_truth_test(true, not_expr1.clone(), expr1?);
+            self.push_internal_code(Operation::IfGoto(not_expr1, label0));
+            // if (!e0) goto L0;
+        }
+        self.set_label(&label1); // L1:
+        self.push_scope(); // the body
+        Ok(())
+    }
+
+    // Parsing switch statement (there is no fall-through)
+    // switch (e0) {
+    // case n0:
+    //     A
+    // case n1:
+    //     B
+    // default:
+    //     C
+    // }
+    // ↓
+    // Copy(v, e0)
+    // Goto(L1)
+    // Ln0:
+    // A
+    // Goto(L0)
+    // Ln1:
+    // B
+    // Goto(L0)
+    // Ld:
+    // C
+    // Goto(L0)
+    // L1:
+    // dispatch to Ln0, Ln1 or Ld (emitted by this function)
+    // L0: (this label is referred by "break")
+    fn compile_switch_end(
+        &mut self,
+        label0: Token,
+        label1: Token,
+        val: Token,
+        cases: Vec<(i64, Token)>,
+        default: Option<Token>,
+    ) {
+        self.push_internal_code(Operation::Goto(label0.clone()));
+        self.set_label(&label1); // L1:
+        let default = default.unwrap_or_else(|| label0.clone());
+        let min = cases.iter().map(|(n, _)| *n).min().unwrap_or(0);
+        let max = cases.iter().map(|(n, _)| *n).max().unwrap_or(0);
+        let range = max as i128 - min as i128 + 1;
+        self.reset_temp_vars();
+        // dense cases use a jump table, sparse ones a chain of comparisons
+        if cases.len() >= JUMP_TABLE_MIN_CASES && range <= 2 * cases.len() as i128 {
+            let mut labels = vec![default.clone(); range as usize];
+            for (n, label) in cases {
+                labels[(n - min) as usize] = label;
+            }
+            self.push_internal_code(Operation::JumpTable(val, min, labels, default));
+        } else {
+            for (n, label) in cases {
+                let cond = self.make_temp_var();
+                self.push_internal_code(Operation::Eq(
+                    cond.clone(),
+                    val.clone(),
+                    Token::new_num(n, None),
+                ));
+                self.push_internal_code(Operation::IfGoto(cond, label));
+            }
+            self.push_internal_code(Operation::Goto(default));
+        }
+        self.set_label(&label0); // L0:
+    }
+
+    // start the body of case or default
+    // the previous body ends with a jump to the end of the switch statement
+    // but an empty body shares the next body (e.g. "case 1: case 2: A")
+    fn switch_case(&mut self, keyword: &str) -> Result<Token, String> {
+        let (label0, prev_is_empty, started) = match self.blocks.last() {
+            Some(Block::Switch(ref label0, _, _, ref cases, ref default, _)) => {
+                let prev_is_empty = match self.labels.last() {
+                    Some((label, line)) if *line == self.internal_code.len() => cases
+                        .iter()
+                        .map(|(_, case_label)| case_label)
+                        .chain(default.iter())
+                        .any(|case_label| case_label == label),
+                    _ => false,
+                };
+                (
+                    label0.clone(),
+                    prev_is_empty,
+                    !cases.is_empty() || default.is_some(),
+                )
+            }
+            _ => return Err(format!("{} outside of switch", keyword)),
+        };
+        if started && !prev_is_empty {
+            self.push_internal_code(Operation::Goto(label0)); // Goto(L0)
+        }
+        // each body has its own scope
+        self.pop_scope();
+        self.push_scope();
+        let label = self.make_temp_label();
+        self.set_label(&label);
+        Ok(label)
+    }
+
+    // find the innermost loop (or switch for break), or the loop named `name` if specified
+    // returns the labels to which break and continue jump
+    fn loop_labels(&self, keyword: &str, name: Option<&Token>) -> Result<(Token, Token), String> {
+        for block in self.blocks.iter().rev() {
+            let (break_label, continue_label, loop_name) = match block {
+                Block::For(ref label0, _, ref label2, _, _, ref loop_name) => {
+                    (label0, label2, loop_name)
+                }
+                Block::While(ref label0, ref label1, ref loop_name) => (label0, label1, loop_name),
+                Block::DoWhile(ref label0, _, ref label2, ref loop_name) => {
+                    (label0, label2, loop_name)
+                }
+                // continue jumps over switch statements to the enclosing loop
+                Block::Switch(ref label0, .., ref loop_name) => {
+                    if keyword == "continue" {
+                        continue;
+                    }
+                    (label0, label0, loop_name)
+                }
+                // cannot jump out of functions
+                Block::Func(_) => break,
+                Block::IfElse(..) => continue,
+            };
+            match (name, loop_name) {
+                (None, _) => return Ok((break_label.clone(), continue_label.clone())),
+                (Some(name), Some(loop_name)) if name.string == loop_name.string => {
+                    return Ok((break_label.clone(), continue_label.clone()))
+                }
+                _ => (),
+            }
+        }
+        match name {
+            Some(name) => Err(format!("Undefined loop label: {}", name.string)),
+            None => Err(format!("{} outside of loop", keyword)),
+        }
+    }
+
     pub fn compile(&mut self, var: &mut VariableMap, is_interactive: bool) -> Result<(), String> {
+        self.lexer.int_width = var.int_width;
+        self.lexer.lex()?;
+        self.scan_functions()?;
+        self.scan_arrays(var);
+        self.scan_structs(var)?;
+        self.scan_struct_vars(var);
+        self.scopes[0].consts = var.consts.clone();
         while self.pos < self.lexer.tokens.len() - 3 {
+            self.cur_line = self.lexer.tokens[self.pos].line;
+            // a label just before a loop is the name of the loop
+            let loop_name = self.last_label.take();
             // (simple) assignment
             if self.phrase_compare(["*t0", "=", "*t1", ";"])? {
                 let param0 = self.cur_token_param[0].take().unwrap();
                 let param1 = self.cur_token_param[1].take().unwrap();
+                // copy of structs
+                if self.struct_copy(&param0, &param1)? {
+                    continue;
+                }
+                // copy of arrays
+                if self.is_array(self.pos - 2) {
+                    if self.is_declared_var(&param0.string) {
+                        return Err(format!(
+                            "Cannot assign array {} to variable {}",
+                            param1.string, param0.string
+                        ));
+                    }
+                    self.push_internal_code(Operation::ArrayCopy(param0, param1));
+                    continue;
+                }
+                if self.is_array(self.pos - 4) {
+                    return Err(format!("Cannot assign a value to array {}", param0.string));
+                }
+                self.check_assignable(&param0)?;
+                let (param0, param1) = (self.resolve_var(param0), self.resolve_var(param1));
                 self.push_internal_code(Operation::Copy(param0, param1));
             }
             // (complicated) assignment (This can interpret the first three syntax)
             else if self.phrase_compare(["*t0", "=", "*e0", ";"])? {
                 let param0 = self.cur_token_param[0].take().unwrap();
+                self.check_assignable(&param0)?;
+                let param0 = self.resolve_var(param0);
                 let expr0 = self.get_expr_param(0)?;
                 self.push_internal_code(Operation::Copy(param0, expr0));
             }
-            // print
-            else if self.phrase_compare(["print", "*e0", ";"])? {
-                let expr0 = self.get_expr_param(0)?;
-                self.push_internal_code(Operation::Print(expr0));
+            // print e0, e1, ...;
+            else if self.phrase_compare(["print"])? {
+                for val in self.expr_list()? {
+                    self.push_internal_code(Operation::Print(val));
+                }
+            }
+            // println; (prints a newline)
+            else if self.phrase_compare(["println", ";"])? {
+                let empty = Token::new(String::new(), TokenType::StrLiteral);
+                self.push_internal_code(Operation::Println(empty));
+            }
+            // println e0, e1, ...;
+            // all but the last values are printed by Print
+            else if self.phrase_compare(["println"])? {
+                let mut vals = self.expr_list()?;
+                let last = vals.pop().unwrap();
+                for val in vals {
+                    self.push_internal_code(Operation::Print(val));
+                }
+                self.push_internal_code(Operation::Println(last));
+            }
+            // printf "format", e0, e1, ...;
+            else if self.phrase_compare(["printf", "*t0", ","])?
+                || self.phrase_compare(["printf", "*t0", ";"])?
+            {
+                let fmt = self.cur_token_param[0].take().unwrap();
+                if fmt.ty != TokenType::StrLiteral {
+                    return Err(String::from(
+                        "The format of printf must be a string literal",
+                    ));
+                }
+                let pieces = format::parse_format(&fmt.string)?;
+                // "printf fmt;" has no arguments
+                let args = if self.lexer.tokens[self.pos - 1].matches(";") {
+                    Vec::new()
+                } else {
+                    self.expr_list()?
+                };
+                if format::count_specs(&pieces) != args.len() {
+                    return Err(format!(
+                        "The format of printf takes {} argument(s) but {} were given",
+                        format::count_specs(&pieces),
+                        args.len()
+                    ));
+                }
+                self.push_internal_code(Operation::Printf(fmt, args));
             }
-            // println
-            else if self.phrase_compare(["println", "*e0", ";"])? {
-                // TODO: Is it valid that e0 is a string literal?
-                // It works because the length of *e0 is at least one.
+            // switch statement (see compile_switch_end)
+            else if self.phrase_compare(["switch", "(", "*e0", ")", "{"])? {
+                let tok = &self.lexer.tokens[self.pos];
+                if !tok.matches("case") && !tok.matches("default") && !tok.matches("}") {
+                    return Err(String::from("Missing case before statements in switch"));
+                }
+                // nested switch statements need their own variables
+                let depth = self
+                    .blocks
+                    .iter()
+                    .filter(|b| matches!(b, Block::Switch(..)))
+                    .count();
+                let val = Token::new(format!("_switch{}", depth), TokenType::Ident);
                 let expr0 = self.get_expr_param(0)?;
-                self.push_internal_code(Operation::Println(expr0));
+                self.push_internal_code(Operation::Copy(val.clone(), expr0));
+                let label0 = self.make_temp_label();
+                let label1 = self.make_temp_label();
+                self.push_internal_code(Operation::Goto(label1.clone())); // Goto(L1)
+                self.blocks.push(Block::Switch(
+                    label0,
+                    label1,
+                    val,
+                    Vec::new(),
+                    None,
+                    loop_name,
+                ));
+                self.push_scope();
+            } else if self.phrase_compare(["case", "*t0", ":"])?
+                || self.phrase_compare(["case", "-", "*t0", ":"])?
+            {
+                let tok = self.cur_token_param[0].take().unwrap();
+                let n = match self.resolve_const(&tok).unwrap_or_else(|| tok.clone()).ty {
+                    TokenType::NumLiteral(n) => n,
+                    _ => return Err(format!("Case value must be a constant: {}", tok.string)),
+                };
+                let n = if self.lexer.tokens[self.pos - 3].matches("-") {
+                    n.wrapping_neg()
+                } else {
+                    n
+                };
+                let label = self.switch_case("case")?;
+                if let Some(Block::Switch(_, _, _, ref mut cases, ..)) = self.blocks.last_mut() {
+                    if cases.iter().any(|(m, _)| *m == n) {
+                        return Err(format!("Duplicate case value: {}", n));
+                    }
+                    cases.push((n, label));
+                }
+            } else if self.phrase_compare(["default", ":"])? {
+                let label = self.switch_case("default")?;
+                if let Some(Block::Switch(_, _, _, _, ref mut default, _)) = self.blocks.last_mut()
+                {
+                    if default.is_some() {
+                        return Err(String::from("Duplicate default in switch"));
+                    }
+                    *default = Some(label);
+                }
             }
             // label
             else if self.phrase_compare(["*t0", ":"])? {
                 let label = self.cur_token_param[0].take().unwrap();
-                var.label_set(&label, self.internal_code.len() as i32);
+                let target = self.user_label(&label);
+                if !self.user_labels.insert(target.string.clone()) {
+                    return Err(format!("Duplicate label: {}", label.string));
+                }
+                self.set_label(&target);
+                self.last_label = Some(label);
                 continue;
             }
             // goto
             else if self.phrase_compare(["goto", "*t0", ";"])? {
                 let param0 = self.cur_token_param[0].take().unwrap();
-                self.push_internal_code(Operation::Goto(param0));
+                let target = self.user_label(&param0);
+                self.gotos.push((param0, target.clone()));
+                self.push_internal_code(Operation::Goto(target));
             }
             // if ( e0 ) goto label;
             else if self.phrase_compare(["if", "(", "*e0", ")", "goto", "*t0", ";"])? {
                 let label = self.cur_token_param[0].take().unwrap();
                 let expr0 = self.get_expr_param(0)?;
-                self.push_internal_code(Operation::IfGoto(expr0, label));
+                let target = self.user_label(&label);
+                self.gotos.push((label, target.clone()));
+                self.push_internal_code(Operation::IfGoto(expr0, target));
             }
             // Parsing "if" statement
             // if (*e0) {
@@ -561,13 +2229,10 @@ impl Parser {
 
                 let expr0 = self.get_expr_param(0)?;
                 let not_expr0 = self.make_temp_var();
-                self.push_internal_code(Operation::Eq(
-                    not_expr0.clone(),
-                    expr0,
-                    Token::new_num(0, None),
-                ));
+                self.push_truth_test(true, not_expr0.clone(), expr0);
                 self.push_internal_code(Operation::IfGoto(not_expr0, label0.clone()));
-            // if (!e0) goto L0;
+                // if (!e0) goto L0;
+                self.push_scope();
             } else if self.phrase_compare(["}", "else", "{"])? {
                 let block = match self.blocks.pop() {
                     Some(b) => b,
@@ -582,48 +2247,79 @@ impl Parser {
                     .push(Block::IfElse(label0.clone(), Some(label1.clone())));
 
                 self.push_internal_code(Operation::Goto(label1)); // Goto(L1)
-                var.label_set(label0, self.internal_code.len() as i32); // L0:
+                self.set_label(label0); // L0:
+                self.pop_scope();
+                self.push_scope();
+            }
+            // for statement (see compile_for)
+            else if self.phrase_compare([
+                "for", "(", "var", "*t0", "=", "*e0", ";", "**e1", ";", "**e2", ")", "{",
+            ])? {
+                // the variable declared in the header is local to the for statement
+                self.push_scope();
+                let ident = self.cur_token_param[0].take().unwrap();
+                let expr0 = self.get_expr_param(0)?;
+                let ident = self.declare_var(&ident)?;
+                self.push_internal_code(Operation::Copy(ident, expr0));
+                self.compile_for(loop_name)?;
+            } else if self
+                .phrase_compare(["for", "(", "**e0", ";", "**e1", ";", "**e2", ")", "{"])?
+            {
+                self.push_scope();
+                self.get_expr_opt_param(0); // evaluate e0
+                self.compile_for(loop_name)?;
             }
-            // Parsing for statement
-            // for (**e0; **e1; **e2) {
+            // Parsing while statement
+            // while (*e0) {
             //     A
             // }
             // ↓
-            // evaluate e0 (output if e0 exists)
-            // IfGoto(!e1, L0) (output if e1 exists)
+            // L1: (this label is referred by "continue")
+            // IfGoto(!e0, L0)
+            // A
+            // Goto(L1)
+            // L0:
+            else if self.phrase_compare(["while", "(", "*e0", ")", "{"])? {
+                let label0 = self.make_temp_label();
+                let label1 = self.make_temp_label();
+                self.set_label(&label1); // L1:
+                let expr0 = self.get_expr_param(0)?;
+                let not_expr0 = self.make_temp_var();
+                self.push_truth_test(true, not_expr0.clone(), expr0);
+                self.push_internal_code(Operation::IfGoto(not_expr0, label0.clone()));
+                // if (!e0) goto L0;
+                self.blocks.push(Block::While(label0, label1, loop_name));
+                self.push_scope();
+            }
+            // Parsing do-while statement
+            // do {
+            //     A
+            // } while (*e0);
+            // ↓
             // L1:
             // A
             // L2: (this label is referred by "continue")
-            // evaluate e2 (output if e2 exists)
-            // IfGoto(e1, L1) (Goto(L1) is output if e1 dosen't exist)
+            // IfGoto(e0, L1)
             // L0:
-            else if self
-                .phrase_compare(["for", "(", "**e0", ";", "**e1", ";", "**e2", ")", "{"])?
-            {
+            else if self.phrase_compare(["do", "{"])? {
                 let label0 = self.make_temp_label();
                 let label1 = self.make_temp_label();
                 let label2 = self.make_temp_label();
-                self.blocks.push(Block::For(
-                    label0.clone(),
-                    label1.clone(),
-                    label2,
-                    self.cur_expr_param_start_pos[1],
-                    self.cur_expr_param_start_pos[2],
-                ));
-
-                self.get_expr_opt_param(0); // evaluate e0
-                let opt_expr1 = self.get_expr_opt_param(1);
-                if let Some(expr1) = opt_expr1 {
-                    let not_expr1 = self.make_temp_var();
-                    self.push_internal_code(Operation::Eq(
-                        not_expr1.clone(),
-                        expr1?,
-                        Token::new_num(0, None),
-                    ));
-                    self.push_internal_code(Operation::IfGoto(not_expr1, label0.clone()));
-                    // if (!e0) goto L0;
+                self.set_label(&label1); // L1:
+                self.blocks
+                    .push(Block::DoWhile(label0, label1, label2, loop_name));
+                self.push_scope();
+            } else if matches!(self.blocks.last(), Some(Block::DoWhile(..)))
+                && self.phrase_compare(["}", "while", "(", "*e0", ")", ";"])?
+            {
+                if let Some(Block::DoWhile(label0, label1, label2, _)) = self.blocks.pop() {
+                    // e0 cannot refer to variables declared in the body
+                    self.pop_scope();
+                    self.set_label(&label2); // L2:
+                    let expr0 = self.get_expr_param(0)?;
+                    self.push_internal_code(Operation::IfGoto(expr0, label1));
+                    self.set_label(&label0); // L0:
                 }
-                var.label_set(&label1, self.internal_code.len() as i32); // L1:
             } else if self.phrase_compare(["}"])? {
                 let block = match self.blocks.pop() {
                     Some(b) => b,
@@ -631,13 +2327,30 @@ impl Parser {
                 };
                 match block {
                     Block::IfElse(ref label0, None) => {
-                        var.label_set(label0, self.internal_code.len() as i32); // L0:
+                        self.set_label(label0); // L0:
                     }
                     Block::IfElse(_, Some(ref label1)) => {
-                        var.label_set(label1, self.internal_code.len() as i32); // L1:
+                        self.set_label(label1); // L1:
                     }
-                    Block::For(ref label0, label1, ref label2, e1_start_pos, e2_start_pos) => {
-                        var.label_set(label2, self.internal_code.len() as i32); // L2:
+                    Block::While(ref label0, label1, _) => {
+                        self.push_internal_code(Operation::Goto(label1)); // Goto(L1)
+                        self.set_label(label0); // L0:
+                    }
+                    Block::DoWhile(..) => {
+                        return Err(String::from("Missing while of do-while statement"));
+                    }
+                    Block::Switch(label0, label1, val, cases, default, _) => {
+                        self.compile_switch_end(label0, label1, val, cases, default);
+                    }
+                    Block::Func(ref label0) => {
+                        // return 0 if the end of the function is reached
+                        self.push_internal_code(Operation::Ret(Token::new_num(0, None)));
+                        self.set_label(label0); // L0:
+                        self.cur_func = None;
+                    }
+                    Block::For(ref label0, label1, ref label2, e1_start_pos, e2_start_pos, _) => {
+                        self.pop_scope(); // the body
+                        self.set_label(label2); // L2:
                         self.evaluate_opt_expr(e2_start_pos);
                         let opt_expr1 = self.evaluate_opt_expr(e1_start_pos);
                         // if e1 (conditions) exists, emits IfGoto otherwise emits Goto
@@ -649,30 +2362,240 @@ impl Parser {
                                 self.push_internal_code(Operation::Goto(label1));
                             }
                         }
-                        var.label_set(label0, self.internal_code.len() as i32); // L0:
+                        self.set_label(label0); // L0:
                     }
                 }
+                self.pop_scope();
+            }
+            // Parsing function definition
+            // func f(params) {
+            //     A
+            // }
+            // ↓
+            // Goto(L0)
+            // f:
+            // A
+            // Ret(0)
+            // L0:
+            else if self.phrase_compare(["func", "*t0"])? {
+                let name = self.cur_token_param[0].take().unwrap();
+                if !self.blocks.is_empty() {
+                    return Err(format!(
+                        "Function {} must be defined at the top level",
+                        name.string
+                    ));
+                }
+                let (params, next_pos) = self.parse_params(self.pos)?;
+                self.pos = next_pos;
+                if !self.phrase_compare(["{"])? {
+                    return Err(format!("Missing body of function {}", name.string));
+                }
+                let label0 = self.make_temp_label();
+                self.blocks.push(Block::Func(label0.clone()));
+                self.push_internal_code(Operation::Goto(label0)); // Goto(L0)
+                let entry = self.functions[&name.string].label.clone();
+                self.set_label(&entry); // f:
+                self.cur_func = Some(entry);
+                // parameters are local to each call, so they are never renamed
+                self.scopes.push(Scope::new(None));
+                for param in &params {
+                    self.declare_var(param)?;
+                }
+            }
+            // struct definitions have been collected by scan_structs
+            else if self.phrase_compare(["struct", "*t0", "{"])? {
+                let name = self.cur_token_param[0].take().unwrap();
+                if !self.blocks.is_empty() {
+                    return Err(format!(
+                        "Struct {} must be defined at the top level",
+                        name.string
+                    ));
+                }
+                while !self.lexer.tokens[self.pos].matches("}") {
+                    self.pos += 1;
+                }
+                self.pos += 1; // "}"
+            }
+            // return
+            else if self.phrase_compare(["return", "**e0", ";"])? {
+                if !self.blocks.iter().any(|b| matches!(b, Block::Func(_))) {
+                    return Err(String::from("return outside of function"));
+                }
+                let val = match self.get_expr_opt_param(0) {
+                    Some(expr0) => expr0?,
+                    None => Token::new_num(0, None),
+                };
+                self.push_internal_code(Operation::Ret(val));
+            }
+            // break and continue
+            else if self.phrase_compare(["break", ";"])? {
+                let (break_label, _) = self.loop_labels("break", None)?;
+                self.push_internal_code(Operation::Goto(break_label));
+            } else if self.phrase_compare(["break", "*t0", ";"])? {
+                let name = self.cur_token_param[0].take().unwrap();
+                let (break_label, _) = self.loop_labels("break", Some(&name))?;
+                self.push_internal_code(Operation::Goto(break_label));
+            } else if self.phrase_compare(["continue", ";"])? {
+                let (_, continue_label) = self.loop_labels("continue", None)?;
+                self.push_internal_code(Operation::Goto(continue_label));
+            } else if self.phrase_compare(["continue", "*t0", ";"])? {
+                let name = self.cur_token_param[0].take().unwrap();
+                let (_, continue_label) = self.loop_labels("continue", Some(&name))?;
+                self.push_internal_code(Operation::Goto(continue_label));
+            }
+            // constant declaration
+            else if self.phrase_compare(["const", "*t0", "=", "*e0", ";"])? {
+                let ident = self.cur_token_param[0].take().unwrap();
+                if is_reserved(&ident) || self.is_array_name(&ident.string) {
+                    return Err(format!("Cannot declare {} as a constant", ident.string));
+                }
+                self.expr_pos = self.cur_expr_param_start_pos[0];
+                let val = self.const_expr(&ident)?;
+                let scope = self.scopes.last_mut().unwrap();
+                if scope.vars.contains_key(&ident.string)
+                    || scope.consts.contains_key(&ident.string)
+                {
+                    return Err(format!(
+                        "Redeclaration of constant {} in the same block",
+                        ident.string
+                    ));
+                }
+                scope.consts.insert(ident.string, val);
+            }
+            // variable declaration
+            else if self.phrase_compare(["var", "*t0", "=", "*e0", ";"])? {
+                let ident = self.cur_token_param[0].take().unwrap();
+                // evaluate e0 before declaring so that e0 refers to the outer variable
+                let expr0 = self.get_expr_param(0)?;
+                let ident = self.declare_var(&ident)?;
+                self.push_internal_code(Operation::Copy(ident, expr0));
+            } else if self.phrase_compare(["var", "*t0", ";"])? {
+                let ident = self.cur_token_param[0].take().unwrap();
+                let ident = self.declare_var(&ident)?;
+                self.push_internal_code(Operation::Copy(ident, Token::new_num(0, None)));
             }
             // time
             else if self.phrase_compare(["time", ";"])? {
                 self.push_internal_code(Operation::Time);
-            } else if self.phrase_compare(["let", "*t0", "[", "*e0", "]", ";"])? {
+            }
+            // let ident: T;
+            // let ident: T = {e0, e1, ...}; (the rest of fields are initialized with 0)
+            else if self.phrase_compare(["let", "*t0", ":", "*t1"])? {
                 let param0 = self.cur_token_param[0].take().unwrap();
-                let expr0 = self.get_expr_param(0)?;
-                self.push_internal_code(Operation::ArrayNew(param0, expr0));
+                let param1 = self.cur_token_param[1].take().unwrap();
+                if is_reserved(&param0) {
+                    return Err(format!("Cannot declare {} as a struct", param0.string));
+                }
+                let fields = self.struct_fields(&param1)?;
+                self.reset_temp_vars();
+                self.expr_pos = self.pos;
+                let mut vals = Vec::new();
+                if self.lexer.tokens[self.expr_pos].matches("=") {
+                    if !self.lexer.tokens[self.expr_pos + 1].matches("{") {
+                        return Err(format!(
+                            "Struct {} must be initialized with a list",
+                            param0.string
+                        ));
+                    }
+                    self.expr_pos += 2; // "=" "{"
+                    vals = self.initializer_list()?;
+                }
+                self.expect_semicolon()?;
+                if vals.len() > fields.len() {
+                    return Err(format!(
+                        "Struct {} has {} field(s) but {} values were given",
+                        param1.string,
+                        fields.len(),
+                        vals.len()
+                    ));
+                }
+                // the values are copied to temporary variables first
+                // so that "let p: T = {p.y, p.x};" swaps the fields
+                let mut tmps = Vec::new();
+                for val in vals {
+                    let tmp = self.make_temp_var();
+                    self.push_internal_code(Operation::Copy(tmp.clone(), val));
+                    tmps.push(tmp);
+                }
+                tmps.resize(fields.len(), Token::new_num(0, None));
+                // declared after the values so that they refer to the outer variable
+                let ident = self.declare_struct(&param0, &param1, false)?;
+                for (field, val) in fields.iter().zip(tmps) {
+                    self.push_internal_code(Operation::Copy(field_of(&ident, field), val));
+                }
             }
-            // assign to array elements (evaluate_expr support array with read-only)
-            else if self.phrase_compare(["*t0", "[", "*e0", "]", "=", "*e1", ";"])? {
+            // let ident[] = {e0, e1, ...};
+            // the size is inferred from the initializer list
+            else if self.phrase_compare(["let", "*t0", "[", "]", "=", "{"])? {
                 let param0 = self.cur_token_param[0].take().unwrap();
-                // Don't reset temp_var_cnt, or it may be the case that _tmp0 is used by e0 and _tmp0 is used by e1.
-                self.temp_var_cnt = 0;
-                self.expr_pos = self.cur_expr_param_start_pos[0];
-                let expr0 = self.expr()?;
-                self.expr_pos = self.cur_expr_param_start_pos[1];
-                let expr1 = self.expr()?;
-
-                self.push_internal_code(Operation::ArraySet(param0, expr0, expr1));
-            } else if self.phrase_compare(["**e0", ";"])? {
+                self.declare_array(&param0)?;
+                self.reset_temp_vars();
+                self.expr_pos = self.pos;
+                let vals = self.initializer_list()?;
+                self.expect_semicolon()?;
+                let size = Token::new_num(vals.len() as i64, None);
+                self.array_new_with_list(param0, vec![size], vals);
+            }
+            // let ident[e0][e1]...;
+            // let ident[e0][e1]... = e; (all elements are initialized with e)
+            // let ident[e0] = {e1, e2, ...}; (the rest of elements are initialized with 0)
+            else if self.phrase_compare(["let", "*t0", "["])? {
+                let param0 = self.cur_token_param[0].take().unwrap();
+                if self.lexer.tokens[self.pos].matches("]") {
+                    return Err(format!("Missing size of array {}", param0.string));
+                }
+                self.reset_temp_vars();
+                self.expr_pos = self.pos - 1;
+                let sizes = self.indices()?;
+                // let ident[e0][e1]...: T; (an array for each field)
+                if self.lexer.tokens[self.expr_pos].matches(":") {
+                    let ty = self.lexer.tokens[self.expr_pos + 1].clone();
+                    self.expr_pos += 2;
+                    self.expect_semicolon()?;
+                    self.declare_struct(&param0, &ty, true)?;
+                    for field in self.struct_fields(&ty)? {
+                        let zero = Token::new_num(0, None);
+                        let array = field_of(&param0, &field);
+                        self.push_internal_code(Operation::ArrayNew(array, sizes.clone(), zero));
+                    }
+                    continue;
+                }
+                self.declare_array(&param0)?;
+                if !self.lexer.tokens[self.expr_pos].matches("=") {
+                    self.expect_semicolon()?;
+                    let zero = Token::new_num(0, None);
+                    self.push_internal_code(Operation::ArrayNew(param0, sizes, zero));
+                } else if self.lexer.tokens[self.expr_pos + 1].matches("{") {
+                    if sizes.len() != 1 {
+                        return Err(format!(
+                            "Only one-dimensional arrays can be initialized with a list: {}",
+                            param0.string
+                        ));
+                    }
+                    self.expr_pos += 2; // "=" "{"
+                    let vals = self.initializer_list()?;
+                    self.expect_semicolon()?;
+                    // sizes which are not constant are checked at runtime
+                    if let TokenType::NumLiteral(n) = sizes[0].ty {
+                        if vals.len() as i64 > n {
+                            return Err(format!(
+                                "Array {} has {} element(s) but {} values were given",
+                                param0.string,
+                                n,
+                                vals.len()
+                            ));
+                        }
+                    }
+                    self.array_new_with_list(param0, sizes, vals);
+                } else {
+                    self.expr_pos += 1; // "="
+                    let val = self.expr()?;
+                    self.expect_semicolon()?;
+                    self.push_internal_code(Operation::ArrayNew(param0, sizes, val));
+                }
+            }
+            // expressions including assignments to array elements
+            else if self.phrase_compare(["**e0", ";"])? {
                 match self.get_expr_opt_param(0) {
                     Some(r) => {
                         r?;
@@ -687,14 +2610,27 @@ impl Parser {
             }
             // report syntax error
             else {
+                let line = match self.lexer.tokens[self.pos].line {
+                    Some(line) => format!(" at line {}", line),
+                    None => String::new(),
+                };
                 return Err(format!(
-                    "Syntax error: {} {} {}",
+                    "Syntax error{}: {} {} {}",
+                    line,
                     self.lexer.tokens[self.pos].string,
                     self.lexer.tokens[self.pos + 1].string,
                     self.lexer.tokens[self.pos + 2].string,
                 ));
             }
         }
+        self.check_gotos(var)?;
+        for (label, line) in &self.labels {
+            var.label_set(label, *line as i32);
+        }
+        var.consts = self.scopes[0].consts.clone();
+        var.structs = self.structs.clone();
+        var.struct_vars = self.scopes[0].struct_vars.clone();
+        var.struct_arrays = self.scopes[0].struct_arrays.clone();
         Ok(())
     }
 
@@ -720,14 +2656,14 @@ impl Parser {
         }
         */
 
-        for i in 0..=self.internal_code.len() {
+        for (i, labels) in label_map.iter().enumerate() {
             //println!("line {}", i);
-            for label in &label_map[i] {
+            for label in labels {
                 println!("{}:", label.string);
             }
             if i != self.internal_code.len() {
                 print!("\t");
-                dump_operation(&self.internal_code[i])
+                dump_operation(&self.internal_code[i], var_map.int_width)
             }
         }
     }
@@ -0,0 +1,379 @@
+use crate::lexer::{Token, TokenType};
+use crate::parser::{Operation, Parser};
+use crate::var_map::VariableMap;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+// types checked by --strict-types
+// (big integers are int, arrays only contain ints)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+}
+
+impl Type {
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::Str => "str",
+        }
+    }
+
+    fn is_number(self) -> bool {
+        self == Type::Int || self == Type::Float
+    }
+}
+
+// places where values are stored
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Slot {
+    // variables (with the name of the function if they are local)
+    Var(Option<String>, String),
+    // temporary variables (they are reused in each statement)
+    Temp(usize, String),
+    // the return value of a function
+    Ret(String),
+}
+
+struct TypeChecker<'a> {
+    parser: &'a Parser,
+    // the function which each operation belongs to
+    funcs: Vec<Option<String>>,
+    // implicit "return 0" at the end of functions
+    implicit_ret: Vec<bool>,
+    types: HashMap<Slot, Type>,
+    // unknown types are errors until all assignments are inferred
+    default_int: bool,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn new(parser: &'a Parser, var_map: &mut VariableMap) -> Self {
+        let len = parser.internal_code.len();
+        let mut funcs = vec![None; len];
+        let mut implicit_ret = vec![false; len];
+        // Goto(L0); f: body; Ret(0); L0:
+        for (name, func) in &parser.functions {
+            let start = var_map.label_get(&func.label) as usize;
+            let end = match parser.internal_code[start - 1] {
+                Operation::Goto(ref label0) => var_map.label_get(label0) as usize,
+                _ => unreachable!(),
+            };
+            for f in &mut funcs[start..end] {
+                *f = Some(name.clone());
+            }
+            implicit_ret[end - 1] = true;
+        }
+        // variables declared in interactive mode
+        let types = var_map
+            .var_types
+            .iter()
+            .map(|(name, ty)| (Slot::Var(None, name.clone()), *ty))
+            .collect();
+        TypeChecker {
+            parser,
+            funcs,
+            implicit_ret,
+            types,
+            default_int: false,
+        }
+    }
+
+    fn slot(&self, i: usize, tok: &Token) -> Option<Slot> {
+        if tok.ty != TokenType::Ident {
+            return None;
+        }
+        if tok.string.starts_with("_tmp") {
+            let gen = self.parser.op_info[i].temp_var_gen;
+            return Some(Slot::Temp(gen, tok.string.clone()));
+        }
+        Some(Slot::Var(self.funcs[i].clone(), tok.string.clone()))
+    }
+
+    fn slot_type(&self, slot: &Slot) -> Result<Type, String> {
+        match self.types.get(slot) {
+            Some(ty) => Ok(*ty),
+            // variables which are never assigned are 0
+            None if self.default_int => Ok(Type::Int),
+            None => Err(String::new()),
+        }
+    }
+
+    fn type_of(&self, i: usize, tok: &Token) -> Result<Type, String> {
+        match tok.ty {
+            TokenType::NumLiteral(_) if tok.string == "true" || tok.string == "false" => {
+                Ok(Type::Bool)
+            }
+            TokenType::NumLiteral(_) | TokenType::BigLiteral(_) => Ok(Type::Int),
+            TokenType::FloatLiteral(_) => Ok(Type::Float),
+            TokenType::StrLiteral => Ok(Type::Str),
+            _ => self.slot_type(&self.slot(i, tok).unwrap()),
+        }
+    }
+
+    fn expect(&self, i: usize, tok: &Token, expected: Type) -> Result<(), String> {
+        let ty = self.type_of(i, tok)?;
+        if ty != expected {
+            return Err(format!(
+                "expected {} but found {}",
+                expected.name(),
+                ty.name()
+            ));
+        }
+        Ok(())
+    }
+
+    // the type of lhs op rhs
+    fn binary(&self, i: usize, sym: &str, lhs: &Token, rhs: &Token) -> Result<Type, String> {
+        let (l, r) = (self.type_of(i, lhs)?, self.type_of(i, rhs)?);
+        let ty = match sym {
+            "+" if l == Type::Str && r == Type::Str => Some(Type::Str),
+            "+" | "-" | "*" | "/" | "%" if l.is_number() && r.is_number() => {
+                if l == Type::Float || r == Type::Float {
+                    Some(Type::Float)
+                } else {
+                    Some(Type::Int)
+                }
+            }
+            "&" | "|" | "^" | "<<" | ">>" if l == Type::Int && r == Type::Int => Some(Type::Int),
+            "==" | "!=" if l == r || (l.is_number() && r.is_number()) => Some(Type::Bool),
+            "<" | "<=" | ">" | ">=" if l == r && (l.is_number() || l == Type::Str) => {
+                Some(Type::Bool)
+            }
+            "<" | "<=" | ">" | ">=" if l.is_number() && r.is_number() => Some(Type::Bool),
+            _ => None,
+        };
+        ty.ok_or_else(|| format!("cannot apply {} to {} and {}", sym, l.name(), r.name()))
+    }
+
+    // the slots which internal_code[i] writes to and their types
+    fn infer(&self, i: usize) -> Result<Vec<(Slot, Type)>, String> {
+        let mut ret = Vec::new();
+        let mut assign = |dist: &Token, ty: Type| {
+            if let Some(slot) = self.slot(i, dist) {
+                ret.push((slot, ty));
+            }
+        };
+        match self.parser.internal_code[i] {
+            Operation::Copy(ref dist, ref val) => assign(dist, self.type_of(i, val)?),
+            Operation::Eq(ref dist, ref val, _) | Operation::Ne(ref dist, ref val, _)
+                if self.parser.op_info[i].truth_test =>
+            {
+                self.expect(i, val, Type::Bool)?;
+                assign(dist, Type::Bool);
+            }
+            Operation::Add(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "+", lhs, rhs)?)
+            }
+            Operation::Sub(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "-", lhs, rhs)?)
+            }
+            Operation::Mul(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "*", lhs, rhs)?)
+            }
+            Operation::Div(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "/", lhs, rhs)?)
+            }
+            Operation::Mod(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "%", lhs, rhs)?)
+            }
+            Operation::BitAnd(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "&", lhs, rhs)?)
+            }
+            Operation::BitOr(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "|", lhs, rhs)?)
+            }
+            Operation::BitXor(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "^", lhs, rhs)?)
+            }
+            Operation::Shl(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "<<", lhs, rhs)?)
+            }
+            Operation::Shr(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, ">>", lhs, rhs)?)
+            }
+            Operation::Eq(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "==", lhs, rhs)?)
+            }
+            Operation::Ne(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "!=", lhs, rhs)?)
+            }
+            Operation::Lt(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "<", lhs, rhs)?)
+            }
+            Operation::Le(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, "<=", lhs, rhs)?)
+            }
+            Operation::Gt(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, ">", lhs, rhs)?)
+            }
+            Operation::Ge(ref dist, ref lhs, ref rhs) => {
+                assign(dist, self.binary(i, ">=", lhs, rhs)?)
+            }
+            Operation::Len(ref dist, ref val) => {
+                self.expect(i, val, Type::Str)?;
+                assign(dist, Type::Int);
+            }
+            Operation::ToInt(ref dist, ref val) | Operation::ToFloat(ref dist, ref val) => {
+                let ty = self.type_of(i, val)?;
+                if !ty.is_number() {
+                    return Err(format!("expected a number but found {}", ty.name()));
+                }
+                match self.parser.internal_code[i] {
+                    Operation::ToInt(..) => assign(dist, Type::Int),
+                    _ => assign(dist, Type::Float),
+                }
+            }
+            Operation::Input(ref dist) => assign(dist, Type::Int),
+            Operation::InputLine(ref dist) => assign(dist, Type::Str),
+            Operation::Eof(ref dist) => assign(dist, Type::Bool),
+            Operation::IfGoto(ref cond, _) => self.expect(i, cond, Type::Bool)?,
+            // the value is compared with the cases like a chain of comparisons
+            Operation::JumpTable(ref val, ..) => {
+                self.binary(i, "==", val, &Token::new_num(0, None))?;
+            }
+            Operation::ArrayNew(_, ref sizes, ref val) => {
+                for size in sizes {
+                    self.expect(i, size, Type::Int)?;
+                }
+                self.expect(i, val, Type::Int)?;
+            }
+            Operation::ArraySet(_, ref indices, ref val) => {
+                for index in indices {
+                    self.expect(i, index, Type::Int)?;
+                }
+                self.expect(i, val, Type::Int)?;
+            }
+            Operation::ArrayGet(ref dist, _, ref indices) => {
+                for index in indices {
+                    self.expect(i, index, Type::Int)?;
+                }
+                assign(dist, Type::Int);
+            }
+            Operation::ArrayLen(ref dist, _) => assign(dist, Type::Int),
+            Operation::ArrayEq(ref dist, ..) => assign(dist, Type::Bool),
+            // arguments are assigned to parameters
+            Operation::Call(ref dist, ref name, ref args) => {
+                let func = &self.parser.functions[&name.string];
+                for (param, arg) in func.params.iter().zip(args) {
+                    let slot = Slot::Var(Some(name.string.clone()), param.string.clone());
+                    ret.push((slot, self.type_of(i, arg)?));
+                }
+                if let (Some(slot), Ok(ty)) = (
+                    self.slot(i, dist),
+                    self.slot_type(&Slot::Ret(name.string.clone())),
+                ) {
+                    ret.push((slot, ty));
+                }
+            }
+            Operation::Ret(ref val) if !self.implicit_ret[i] => {
+                let func = self.funcs[i].clone().unwrap();
+                ret.push((Slot::Ret(func), self.type_of(i, val)?));
+            }
+            _ => {}
+        }
+        Ok(ret)
+    }
+
+    // a temporary variable is assigned twice in the same statement
+    // by both sides of ?: or by a compound assignment to an array element
+    fn temp_conflict(&self, i: usize, expected: &str, ty: &str) -> String {
+        let code = &self.parser.internal_code;
+        // ArrayGet(t, a, i); Add(t, t, e); ArraySet(a, i, t)
+        if let Some(Operation::ArraySet(ref name, _, _)) = code.get(i + 1) {
+            if !matches!(code[i], Operation::Copy(..)) {
+                return format!(
+                    "elements of array {} are {} but {} is assigned",
+                    name.string, expected, ty
+                );
+            }
+        }
+        format!(
+            "both sides of ?: must have the same type but found {} and {}",
+            expected, ty
+        )
+    }
+
+    // types are fixed by the first assignment
+    fn check(&mut self) -> Result<(), String> {
+        let len = self.parser.internal_code.len();
+        loop {
+            let mut changed = false;
+            for i in 0..len {
+                for (slot, ty) in self.infer(i).unwrap_or_default() {
+                    if let Entry::Vacant(e) = self.types.entry(slot) {
+                        e.insert(ty);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        self.default_int = true;
+        for i in 0..len {
+            let line = self.parser.op_info[i].line;
+            let error = |msg: String| match line {
+                Some(line) => format!("Type error: {} at line {}", msg, line),
+                None => format!("Type error: {}", msg),
+            };
+            for (slot, ty) in self.infer(i).map_err(error)? {
+                let expected = match self.types.get(&slot) {
+                    Some(expected) => *expected,
+                    None => {
+                        self.types.insert(slot, ty);
+                        continue;
+                    }
+                };
+                if ty == expected {
+                    continue;
+                }
+                let (expected, ty) = (expected.name(), ty.name());
+                return Err(error(match slot {
+                    Slot::Var(Some(func), ref name)
+                        if self.parser.functions[&func]
+                            .params
+                            .iter()
+                            .any(|p| p.matches(name)) =>
+                    {
+                        format!(
+                            "parameter {} of {} is {} but {} is given",
+                            name, func, expected, ty
+                        )
+                    }
+                    Slot::Var(_, name) => {
+                        // variables in blocks are renamed to "name@id"
+                        let name = name.split('@').next().unwrap();
+                        format!("{} is {} but {} is assigned", name, expected, ty)
+                    }
+                    Slot::Temp(..) => self.temp_conflict(i, expected, ty),
+                    Slot::Ret(func) => format!(
+                        "function {} returns {} but {} is returned",
+                        func, expected, ty
+                    ),
+                }));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Parser {
+    // infer the types of variables and check operations before optimization
+    // (the types of global variables are kept for the interactive mode)
+    pub fn check_types(&self, var_map: &mut VariableMap) -> Result<(), String> {
+        let mut checker = TypeChecker::new(self, var_map);
+        checker.check()?;
+        for (slot, ty) in checker.types {
+            if let Slot::Var(None, name) = slot {
+                var_map.var_types.insert(name, ty);
+            }
+        }
+        Ok(())
+    }
+}
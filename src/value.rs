@@ -0,0 +1,263 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::bigint::BigInt;
+
+// values of variables at runtime
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    // integers which do not fit in i64 (only in the big integer mode)
+    Big(BigInt),
+    // always finite
+    Float(f64),
+    Str(String),
+}
+
+// the width of integers (selected by the -int option)
+// integers are stored in i64 and wrapped around at the width
+// In the big integer mode, they never overflow and become Value::Big if they do not fit in i64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntWidth {
+    I32,
+    I64,
+    Big,
+}
+
+// arithmetic operations on numbers (integers, big integers and floats)
+#[derive(Debug, Clone, Copy)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl ArithOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            ArithOp::Add => "+",
+            ArithOp::Sub => "-",
+            ArithOp::Mul => "*",
+            ArithOp::Div => "/",
+            ArithOp::Mod => "%",
+        }
+    }
+}
+
+impl IntWidth {
+    // bit operations and shifts of big integers are done in 64 bits
+    pub fn bits(self) -> u32 {
+        match self {
+            IntWidth::I32 => 32,
+            IntWidth::I64 | IntWidth::Big => 64,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IntWidth::I32 => "i32",
+            IntWidth::I64 => "i64",
+            IntWidth::Big => "big",
+        }
+    }
+
+    // the value of n in the width (two's complement)
+    pub fn wrap(self, n: i64) -> i64 {
+        match self {
+            IntWidth::I32 => n as i32 as i64,
+            IntWidth::I64 | IntWidth::Big => n,
+        }
+    }
+
+    pub fn contains(self, n: i64) -> bool {
+        self.wrap(n) == n
+    }
+
+    // integers read by input (None if s is not an integer in the width)
+    pub fn parse(self, s: &str) -> Option<Value> {
+        match s.parse::<i64>() {
+            Ok(n) if self.contains(n) => Some(Value::Int(n)),
+            Err(_) if self == IntWidth::Big => {
+                let (neg, digits) = match s.strip_prefix('-') {
+                    Some(digits) => (true, digits),
+                    None => (false, s.strip_prefix('+').unwrap_or(s)),
+                };
+                let n = BigInt::parse(digits, 10)?;
+                Some(Value::from_big(if neg { -&n } else { n }))
+            }
+            _ => None,
+        }
+    }
+
+    // lhs op rhs
+    // integers are converted to floats if either operand is a float
+    pub fn arith(self, op: ArithOp, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+        let type_error = || {
+            format!(
+                "Type error: cannot apply {} to {} and {}",
+                op.symbol(),
+                lhs.type_name(),
+                rhs.type_name()
+            )
+        };
+        if !lhs.is_number() || !rhs.is_number() {
+            return Err(type_error());
+        }
+        if rhs.is_zero() {
+            match op {
+                ArithOp::Div => return Err(String::from("Zero division error")),
+                ArithOp::Mod => return Err(String::from("Modulo by zero")),
+                _ => (),
+            }
+        }
+        if let (Value::Float(_), _) | (_, Value::Float(_)) = (lhs, rhs) {
+            let (l, r) = (lhs.to_f64().unwrap(), rhs.to_f64().unwrap());
+            let ret = match op {
+                ArithOp::Add => l + r,
+                ArithOp::Sub => l - r,
+                ArithOp::Mul => l * r,
+                ArithOp::Div => l / r,
+                ArithOp::Mod => l % r,
+            };
+            if !ret.is_finite() {
+                return Err(String::from("Float overflow"));
+            }
+            return Ok(Value::Float(ret));
+        }
+        if let (Value::Int(l), Value::Int(r)) = (lhs, rhs) {
+            let (l, r) = (*l, *r);
+            let checked = match op {
+                ArithOp::Add => l.checked_add(r),
+                ArithOp::Sub => l.checked_sub(r),
+                ArithOp::Mul => l.checked_mul(r),
+                ArithOp::Div => l.checked_div(r),
+                ArithOp::Mod => l.checked_rem(r),
+            };
+            let n = match (checked, self) {
+                (Some(n), _) => n,
+                // overflows in i64
+                (None, IntWidth::Big) => return Ok(arith_big(op, lhs, rhs)),
+                (None, _) => match op {
+                    ArithOp::Add => l.wrapping_add(r),
+                    ArithOp::Sub => l.wrapping_sub(r),
+                    ArithOp::Mul => l.wrapping_mul(r),
+                    ArithOp::Div => l.wrapping_div(r),
+                    ArithOp::Mod => l.wrapping_rem(r),
+                },
+            };
+            return Ok(Value::Int(self.wrap(n)));
+        }
+        Ok(arith_big(op, lhs, rhs))
+    }
+
+    // int(val): floats are truncated toward zero
+    pub fn to_int(self, val: &Value) -> Result<Value, String> {
+        match val {
+            Value::Int(_) | Value::Big(_) => Ok(val.clone()),
+            // i64::MIN as f64 is -2^63 exactly
+            Value::Float(f) if f.trunc() >= i64::MIN as f64 && f.trunc() < -(i64::MIN as f64) => {
+                let n = f.trunc() as i64;
+                if self.contains(n) {
+                    Ok(Value::Int(n))
+                } else {
+                    Err(format!("Cannot convert {} to int", val))
+                }
+            }
+            Value::Float(_) => Err(format!("Cannot convert {} to int", val)),
+            Value::Str(_) => Err(String::from("Type error: cannot apply int to str")),
+        }
+    }
+}
+
+// both operands are integers
+fn arith_big(op: ArithOp, lhs: &Value, rhs: &Value) -> Value {
+    let (l, r) = (lhs.to_big().unwrap(), rhs.to_big().unwrap());
+    let ret = match op {
+        ArithOp::Add => &l + &r,
+        ArithOp::Sub => &l - &r,
+        ArithOp::Mul => &l * &r,
+        ArithOp::Div => l.div_rem(&r).0,
+        ArithOp::Mod => l.div_rem(&r).1,
+    };
+    Value::from_big(ret)
+}
+
+// compare numbers (None if an operand is not a number)
+// integers are converted to floats if either operand is a float
+pub fn cmp_numbers(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Int(l), Value::Int(r)) => Some(l.cmp(r)),
+        (Value::Float(_), _) | (_, Value::Float(_)) => lhs.to_f64()?.partial_cmp(&rhs.to_f64()?),
+        _ => Some(lhs.to_big()?.cmp(&rhs.to_big()?)),
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::Big(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+        }
+    }
+
+    // big integers which fit in i64 are stored as Value::Int
+    pub fn from_big(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(n) => Value::Int(n),
+            None => Value::Big(n),
+        }
+    }
+
+    pub fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(n) => Some(BigInt::from_i64(*n)),
+            Value::Big(n) => Some(n.clone()),
+            Value::Float(_) | Value::Str(_) => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::Big(n) => Some(n.to_f64()),
+            Value::Float(f) => Some(*f),
+            Value::Str(_) => None,
+        }
+    }
+
+    // float(val)
+    pub fn to_float(&self) -> Result<Value, String> {
+        match self.to_f64() {
+            Some(f) if f.is_finite() => Ok(Value::Float(f)),
+            Some(_) => Err(format!("Cannot convert {} to float", self)),
+            None => Err(String::from("Type error: cannot apply float to str")),
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        !matches!(self, Value::Str(_))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Value::Int(n) => *n == 0,
+            Value::Float(f) => *f == 0.0,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Big(n) => write!(f, "{}", n),
+            // the shortest representation which has a decimal point or an exponent
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
@@ -1,14 +1,72 @@
 use crate::error::error_exit;
 use crate::lexer::{Token, TokenType};
+use crate::type_check::Type;
+use crate::value::{IntWidth, Value};
 use std::collections::HashMap;
 
+// the maximum number of elements of an array (512 MiB)
+const MAX_ARRAY_LEN: usize = 1 << 26;
+
+// multi-dimensional arrays are stored in row-major order
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Array {
+    dims: Vec<usize>,
+    elems: Vec<i64>,
+}
+
+impl Array {
+    // the position of the element in elems
+    fn offset(&self, name: &str, indices: &[i64]) -> usize {
+        if indices.len() != self.dims.len() {
+            error_exit(format!(
+                "Array {} has {} dimension(s) but {} index(es) were given",
+                name,
+                self.dims.len(),
+                indices.len()
+            ));
+        }
+        let mut offset = 0;
+        for (i, (index, dim)) in indices.iter().zip(&self.dims).enumerate() {
+            if *index < 0 || *index as u64 >= *dim as u64 {
+                if self.dims.len() == 1 {
+                    error_exit(format!(
+                        "Index out of bounds: the len of {} is {} but the index is {}",
+                        name, dim, index
+                    ));
+                }
+                error_exit(format!(
+                    "Index out of bounds: the len of dimension {} of {} is {} but the index is {}",
+                    i + 1,
+                    name,
+                    dim,
+                    index
+                ));
+            }
+            offset = offset * dim + *index as usize;
+        }
+        offset
+    }
+}
+
 #[derive(Debug)]
 pub struct VariableMap {
-    // integer variables.
+    // variables.
     // Also used for branch labels.
-    pub map: HashMap<String, i32>,
+    pub map: HashMap<String, Value>,
+    // local variables of each function call (the last one is the current frame)
+    frames: Vec<HashMap<String, Value>>,
     // integer arrays
-    array_map: HashMap<String, Vec<i32>>,
+    array_map: HashMap<String, Array>,
+    // constants declared at the top level (kept for the interactive mode)
+    pub consts: HashMap<String, Token>,
+    // types of global variables inferred by --strict-types (kept for the interactive mode)
+    pub var_types: HashMap<String, Type>,
+    // structs and variables and arrays of structs (kept for the interactive mode)
+    pub structs: HashMap<String, Vec<String>>,
+    pub struct_vars: HashMap<String, String>,
+    pub struct_arrays: HashMap<String, String>,
+    // integers are wrapped around at this width
+    pub int_width: IntWidth,
     // label_map["label"] represents the number of the line immidiately following label:
     pub label_map: HashMap<String, i32>,
 }
@@ -17,69 +75,163 @@ impl VariableMap {
     pub fn new() -> Self {
         VariableMap {
             map: HashMap::new(),
+            frames: Vec::new(),
             array_map: HashMap::new(),
+            consts: HashMap::new(),
+            var_types: HashMap::new(),
+            structs: HashMap::new(),
+            struct_vars: HashMap::new(),
+            struct_arrays: HashMap::new(),
+            int_width: IntWidth::I32,
             label_map: HashMap::new(),
         }
     }
 
+    // variables visible from the current function call (or global variables)
+    fn scope_mut(&mut self) -> &mut HashMap<String, Value> {
+        match self.frames.last_mut() {
+            Some(frame) => frame,
+            None => &mut self.map,
+        }
+    }
+
+    pub fn push_frame(&mut self) {
+        self.frames.push(HashMap::new());
+    }
+
+    pub fn pop_frame(&mut self) {
+        self.frames.pop();
+    }
+
+    pub fn frame_depth(&self) -> usize {
+        self.frames.len()
+    }
+
     // TODO: to_string() is a bottleneck
-    pub fn get(&mut self, tok: &Token) -> i32 {
+    pub fn get_value(&mut self, tok: &Token) -> Value {
         match tok.ty {
-            TokenType::NumLiteral(n) => n,
+            TokenType::NumLiteral(n) => Value::Int(n),
+            TokenType::BigLiteral(ref n) => Value::Big(n.clone()),
+            TokenType::FloatLiteral(bits) => Value::Float(f64::from_bits(bits)),
+            TokenType::StrLiteral => Value::Str(tok.string.clone()),
             // undeclared valriables
-            TokenType::Ident => match self.map.get(&tok.string) {
-                Some(n) => *n,
-                None => {
-                    self.map.insert(tok.string.to_string(), 0);
-                    0
+            TokenType::Ident => {
+                let scope = self.scope_mut();
+                match scope.get(&tok.string) {
+                    Some(val) => val.clone(),
+                    None => {
+                        scope.insert(tok.string.to_string(), Value::Int(0));
+                        Value::Int(0)
+                    }
                 }
-            },
+            }
             _ => panic!(),
         }
     }
 
+    // get an integer value (strings are type errors)
+    // big integers cannot be used where i64 is required
+    pub fn get(&mut self, tok: &Token) -> i64 {
+        match self.get_value(tok) {
+            Value::Int(n) => n,
+            Value::Big(n) => error_exit(format!("Integer {} does not fit in 64 bits", n)),
+            val => error_exit(format!(
+                "Type error: expected int but found {}",
+                val.type_name()
+            )),
+        }
+    }
+
     // TODO: to_string() is a bottleneck
-    pub fn set(&mut self, tok: &Token, val: i32) {
-        self.map.insert(tok.string.to_string(), val);
-    }
-
-    // TODO: initialize with specified value
-    pub fn array_init(&mut self, ident: &Token, size: usize) {
-        self.array_map.remove(&ident.string);
-        self.array_map.insert(ident.string.clone(), vec![0; size]);
-    }
-
-    pub fn array_get(&mut self, ident: &Token, index: usize) -> i32 {
-        let arr = self
-            .array_map
-            .get(&ident.string)
-            .unwrap_or_else(|| error_exit(format!("Undeclared array: {}", ident.string)));
-        if index >= arr.len() {
-            panic!(
-                "Index out of bounds: the len of {} is {} but the index is {}",
-                ident.string,
-                arr.len(),
-                index
-            );
+    pub fn set_value(&mut self, tok: &Token, val: Value) {
+        let val = match val {
+            Value::Int(n) => Value::Int(self.int_width.wrap(n)),
+            val => val,
+        };
+        self.scope_mut().insert(tok.string.to_string(), val);
+    }
+
+    pub fn set(&mut self, tok: &Token, val: i64) {
+        self.set_value(tok, Value::Int(val));
+    }
+
+    // all elements are initialized with val
+    pub fn array_init(&mut self, ident: &Token, dims: &[i64], val: i64) -> Result<(), String> {
+        let mut len: usize = 1;
+        for dim in dims {
+            if *dim < 0 {
+                return Err(format!("Invalid size of array {}: {}", ident.string, dim));
+            }
+            len = len
+                .checked_mul(*dim as usize)
+                .filter(|len| *len <= MAX_ARRAY_LEN)
+                .ok_or_else(|| {
+                    format!(
+                        "Array {} is too large: the number of elements must be at most {}",
+                        ident.string, MAX_ARRAY_LEN
+                    )
+                })?;
         }
-        arr[index]
+        let arr = Array {
+            dims: dims.iter().map(|dim| *dim as usize).collect(),
+            elems: vec![val; len],
+        };
+        self.array_map.insert(ident.string.clone(), arr);
+        Ok(())
     }
 
-    pub fn array_set(&mut self, ident: &Token, index: usize, val: i32) {
-        let mut arr = self
-            .array_map
-            .remove(&ident.string)
-            .unwrap_or_else(|| error_exit(format!("Undeclared array: {}", ident.string)));
-        if index >= arr.len() {
-            error_exit(format!(
-                "Index out of bounds: the len of {} is {} but the index is {}",
-                ident.string,
-                arr.len(),
-                index
-            ));
+    fn array_mut(&mut self, ident: &Token) -> &mut Array {
+        self.array_map
+            .get_mut(&ident.string)
+            .unwrap_or_else(|| error_exit(format!("Undeclared array: {}", ident.string)))
+    }
+
+    pub fn array_names(&self) -> impl Iterator<Item = &String> {
+        self.array_map.keys()
+    }
+
+    // the number of characters of a string
+    // len(a) of an array which is never declared is also compiled into this
+    pub fn str_len(&mut self, tok: &Token) -> Result<i64, String> {
+        if tok.ty == TokenType::Ident && !self.scope_mut().contains_key(&tok.string) {
+            return Err(format!("Undeclared array: {}", tok.string));
+        }
+        match self.get_value(tok) {
+            Value::Str(s) => Ok(s.chars().count() as i64),
+            val => Err(format!(
+                "Type error: cannot apply len to {}",
+                val.type_name()
+            )),
         }
-        arr[index] = val;
-        self.array_map.insert(ident.string.to_string(), arr);
+    }
+
+    // the size of the first dimension
+    pub fn array_len(&mut self, ident: &Token) -> i64 {
+        self.array_mut(ident).dims[0] as i64
+    }
+
+    // deep copy
+    pub fn array_copy(&mut self, dist: &Token, ident: &Token) {
+        let arr = self.array_mut(ident).clone();
+        self.array_map.insert(dist.string.clone(), arr);
+    }
+
+    // arrays are equal if their sizes and elements are equal
+    pub fn array_eq(&mut self, lhs: &Token, rhs: &Token) -> bool {
+        let lhs = self.array_mut(lhs).clone();
+        lhs == *self.array_mut(rhs)
+    }
+
+    pub fn array_get(&mut self, ident: &Token, indices: &[i64]) -> i64 {
+        let arr = self.array_mut(ident);
+        let offset = arr.offset(&ident.string, indices);
+        arr.elems[offset]
+    }
+
+    pub fn array_set(&mut self, ident: &Token, indices: &[i64], val: i64) {
+        let arr = self.array_mut(ident);
+        let offset = arr.offset(&ident.string, indices);
+        arr.elems[offset] = val;
     }
 
     // TODO: to_string() is a bottleneck
@@ -101,10 +253,63 @@ mod var_map_tests {
     use super::*;
     use crate::lexer::TokenType;
 
+    #[test]
+    fn test_array() {
+        let mut var = VariableMap::new();
+        let m = Token::new(String::from("m"), TokenType::Ident);
+        var.array_init(&m, &[3, 4], 0).unwrap();
+        var.array_set(&m, &[1, 2], 5);
+        var.array_set(&m, &[2, 3], 7);
+        assert_eq!(var.array_get(&m, &[1, 2]), 5);
+        assert_eq!(var.array_get(&m, &[2, 3]), 7);
+        assert_eq!(var.array_get(&m, &[2, 1]), 0);
+        assert_eq!(var.array_mut(&m).elems[6], 5);
+    }
+
+    #[test]
+    fn test_array_size() {
+        let mut var = VariableMap::new();
+        let a = Token::new(String::from("a"), TokenType::Ident);
+        assert_eq!(
+            var.array_init(&a, &[-1], 0),
+            Err(String::from("Invalid size of array a: -1"))
+        );
+        let too_large = Err(format!(
+            "Array a is too large: the number of elements must be at most {}",
+            MAX_ARRAY_LEN
+        ));
+        assert_eq!(var.array_init(&a, &[1 << 20, 1 << 20], 0), too_large);
+        assert_eq!(
+            var.array_init(&a, &[1 << 40, 1 << 40, 1 << 40], 0),
+            too_large
+        );
+        assert_eq!(var.array_init(&a, &[0, 1 << 40], 0), Ok(()));
+        assert_eq!(var.array_len(&a), 0);
+    }
+
+    #[test]
+    fn test_str_len() {
+        let mut var = VariableMap::new();
+        let s = Token::new(String::from("s"), TokenType::Ident);
+        var.set_value(&s, Value::Str(String::from("abc")));
+        assert_eq!(var.str_len(&s), Ok(3));
+        var.set(&s, 1);
+        assert_eq!(
+            var.str_len(&s),
+            Err(String::from("Type error: cannot apply len to int"))
+        );
+        let zz = Token::new(String::from("zz"), TokenType::Ident);
+        assert_eq!(var.str_len(&zz), Err(String::from("Undeclared array: zz")));
+    }
+
     #[test]
     fn test_numerical_literals() {
         let mut var = VariableMap::new();
         assert_eq!(var.get(&Token::new_num(100, None)), 100);
         assert_eq!(var.get(&Token::new(String::from("a"), TokenType::Ident)), 0);
+        assert_eq!(
+            var.get_value(&Token::new(String::from("abc"), TokenType::StrLiteral)),
+            Value::Str(String::from("abc"))
+        );
     }
 }